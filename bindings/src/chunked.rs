@@ -0,0 +1,326 @@
+use std::borrow::Cow;
+use std::ops;
+
+use regex_cursor::Cursor;
+
+use crate::utf16::utf16_chars;
+use crate::{Input, InputEncoding, IntoInput};
+
+/// Text which is stored as a sequence of chunks, for example a rope or a piece table.
+///
+/// This is the extension point for plugging a text data structure into the parser and query
+/// cursor: implementing it for a (cheaply copyable) handle to the text is enough to get an
+/// [`Input`] through [`ChunkedInput`]. Implementations for `ropey` and `crop` ropes are
+/// available behind the cargo features of the same name.
+///
+/// The text is UTF-8 unless [`ChunkedText::encoding`] says otherwise. Byte offsets always
+/// count bytes of the text's own encoding, so for UTF-16 text they are twice the code unit
+/// index. `&[u16]` is UTF-16 text in the native endianness.
+pub trait ChunkedText<'a>: Copy {
+    /// The total length of the text in bytes.
+    fn len_bytes(self) -> usize;
+
+    /// Returns a chunk which contains the byte at `byte_idx` together with the byte offset at
+    /// which the chunk starts.
+    ///
+    /// The chunk does not need to match the internal chunks of the data structure exactly, it
+    /// may start anywhere before (or at) `byte_idx` and overlap with other chunks. It must never
+    /// be empty unless the text is empty. If `byte_idx` equals [`ChunkedText::len_bytes`] the
+    /// last chunk is returned.
+    ///
+    /// Chunks must start and end at codepoint boundaries: neither the tree-sitter lexer nor
+    /// the regex engine can decode a codepoint which is split across chunks.
+    fn chunk_at_byte(self, byte_idx: usize) -> (&'a [u8], usize);
+
+    /// The encoding of the chunks. Only UTF-8 and UTF-16 are supported.
+    fn encoding(self) -> InputEncoding {
+        InputEncoding::Utf8
+    }
+
+    /// Returns the text within `byte_range`.
+    ///
+    /// UTF-8 text is borrowed when it is contained within a single chunk, UTF-16 text is
+    /// always transcoded to UTF-8. Invalid UTF-8 and unpaired surrogates are replaced with
+    /// `U+FFFD`.
+    fn byte_text(self, byte_range: ops::Range<usize>) -> Cow<'a, str> {
+        let encoding = self.encoding();
+        if encoding != InputEncoding::Utf8 {
+            let mut bytes = Vec::with_capacity(byte_range.len());
+            for_each_chunk(self, byte_range, |chunk| bytes.extend_from_slice(chunk));
+            let big_endian = encoding == InputEncoding::Utf16BE;
+            return utf16_chars(&bytes, big_endian).map(|(c, _)| c).collect();
+        }
+        let (chunk, chunk_start) = self.chunk_at_byte(byte_range.start);
+        let start = byte_range.start - chunk_start;
+        if byte_range.end - chunk_start <= chunk.len() {
+            return String::from_utf8_lossy(&chunk[start..byte_range.end - chunk_start]);
+        }
+        let mut bytes = Vec::with_capacity(byte_range.len());
+        for_each_chunk(self, byte_range, |chunk| bytes.extend_from_slice(chunk));
+        Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Calls `f` with the consecutive pieces of `text` which make up `byte_range`.
+fn for_each_chunk<'a>(
+    text: impl ChunkedText<'a>,
+    byte_range: ops::Range<usize>,
+    mut f: impl FnMut(&'a [u8]),
+) {
+    let mut pos = byte_range.start;
+    while pos < byte_range.end {
+        let (chunk, chunk_start) = text.chunk_at_byte(pos);
+        let end = byte_range.end.min(chunk_start + chunk.len());
+        if end <= pos {
+            // buggy `chunk_at_byte` implementation
+            break;
+        }
+        f(&chunk[pos - chunk_start..end - chunk_start]);
+        pos = end;
+    }
+}
+
+impl<'a> ChunkedText<'a> for &'a str {
+    fn len_bytes(self) -> usize {
+        self.len()
+    }
+
+    fn chunk_at_byte(self, _byte_idx: usize) -> (&'a [u8], usize) {
+        (self.as_bytes(), 0)
+    }
+}
+
+impl<'a> ChunkedText<'a> for &'a String {
+    fn len_bytes(self) -> usize {
+        self.len()
+    }
+
+    fn chunk_at_byte(self, _byte_idx: usize) -> (&'a [u8], usize) {
+        (self.as_bytes(), 0)
+    }
+}
+
+impl<'a> ChunkedText<'a> for &'a [u8] {
+    fn len_bytes(self) -> usize {
+        self.len()
+    }
+
+    fn chunk_at_byte(self, _byte_idx: usize) -> (&'a [u8], usize) {
+        (self, 0)
+    }
+}
+
+impl<'a> IntoInput for &'a str {
+    type Input = ChunkedInput<'a, &'a str>;
+
+    fn into_input(self) -> Self::Input {
+        ChunkedInput::new(self)
+    }
+}
+
+impl<'a> IntoInput for &'a String {
+    type Input = ChunkedInput<'a, &'a String>;
+
+    fn into_input(self) -> Self::Input {
+        ChunkedInput::new(self)
+    }
+}
+
+impl<'a> IntoInput for &'a [u16] {
+    type Input = ChunkedInput<'a, &'a [u16]>;
+
+    fn into_input(self) -> Self::Input {
+        ChunkedInput::new(self)
+    }
+}
+
+impl<'a> IntoInput for &'a [u8] {
+    type Input = ChunkedInput<'a, &'a [u8]>;
+
+    fn into_input(self) -> Self::Input {
+        ChunkedInput::new(self)
+    }
+}
+
+impl IntoInput for String {
+    type Input = StringInput;
+
+    fn into_input(self) -> Self::Input {
+        StringInput::new(self)
+    }
+}
+
+/// An [`Input`] (and [`Cursor`]) which owns a UTF-8 `String`, for example to move the text
+/// into a [`QueryCursor`](crate::QueryCursor) together with the query.
+#[derive(Debug, Clone)]
+pub struct StringInput {
+    text: String,
+}
+
+impl StringInput {
+    pub fn new(text: String) -> Self {
+        StringInput { text }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_inner(self) -> String {
+        self.text
+    }
+}
+
+impl Cursor for StringInput {
+    fn chunk(&self) -> &[u8] {
+        self.text.as_bytes()
+    }
+
+    fn advance(&mut self) -> bool {
+        false
+    }
+
+    fn backtrack(&mut self) -> bool {
+        false
+    }
+
+    fn total_bytes(&self) -> Option<usize> {
+        Some(self.text.len())
+    }
+
+    fn offset(&self) -> usize {
+        0
+    }
+}
+
+impl Input for StringInput {
+    type Cursor = Self;
+
+    fn cursor_at(&mut self, offset: u32) -> &mut Self {
+        debug_assert!(
+            offset as usize <= self.text.len(),
+            "parser offset out of bounds: {offset} > {}",
+            self.text.len()
+        );
+        self
+    }
+
+    fn eq(&mut self, range1: ops::Range<u32>, range2: ops::Range<u32>) -> bool {
+        let bytes = self.text.as_bytes();
+        bytes[range1.start as usize..range1.end as usize]
+            == bytes[range2.start as usize..range2.end as usize]
+    }
+}
+
+/// An [`Input`] (and [`Cursor`]) for any [`ChunkedText`].
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkedInput<'a, T> {
+    text: T,
+    chunk: &'a [u8],
+    offset: usize,
+}
+
+impl<'a, T: ChunkedText<'a>> ChunkedInput<'a, T> {
+    pub fn new(text: T) -> Self {
+        let (chunk, offset) = text.chunk_at_byte(0);
+        ChunkedInput {
+            text,
+            chunk,
+            offset,
+        }
+    }
+
+    pub fn text(&self) -> T {
+        self.text
+    }
+
+    fn seek(&mut self, byte_idx: usize) {
+        (self.chunk, self.offset) = self.text.chunk_at_byte(byte_idx);
+    }
+}
+
+impl<'a, T: ChunkedText<'a>> Cursor for ChunkedInput<'a, T> {
+    fn chunk(&self) -> &[u8] {
+        self.chunk
+    }
+
+    // regexes never run on the raw UTF-16 bytes, see `decode_utf16`
+    fn utf8_aware(&self) -> bool {
+        self.text.encoding() == InputEncoding::Utf8
+    }
+
+    fn advance(&mut self) -> bool {
+        let end = self.offset + self.chunk.len();
+        if end >= self.text.len_bytes() {
+            return false;
+        }
+        self.seek(end);
+        // `chunk_at_byte` may return a chunk which overlaps with the previous one.
+        self.chunk = &self.chunk[end - self.offset..];
+        self.offset = end;
+        true
+    }
+
+    fn backtrack(&mut self) -> bool {
+        let start = self.offset;
+        if start == 0 {
+            return false;
+        }
+        self.seek(start - 1);
+        self.chunk = &self.chunk[..start - self.offset];
+        true
+    }
+
+    fn total_bytes(&self) -> Option<usize> {
+        Some(self.text.len_bytes())
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a, T: ChunkedText<'a>> Input for ChunkedInput<'a, T> {
+    type Cursor = Self;
+
+    fn cursor_at(&mut self, offset: u32) -> &mut Self {
+        let offset = offset as usize;
+        debug_assert!(
+            offset <= self.text.len_bytes(),
+            "parser offset out of bounds: {offset} > {}",
+            self.text.len_bytes()
+        );
+        let chunk_end = self.offset + self.chunk.len();
+        // Reading the end of the text is allowed to return the last chunk.
+        let in_chunk = offset < chunk_end || chunk_end == self.text.len_bytes();
+        if offset < self.offset || !in_chunk {
+            self.seek(offset);
+        }
+        self
+    }
+
+    fn eq(&mut self, range1: ops::Range<u32>, range2: ops::Range<u32>) -> bool {
+        if range1.len() != range2.len() {
+            return false;
+        }
+        let range1 = range1.start as usize..range1.end as usize;
+        let range2 = range2.start as usize..range2.end as usize;
+        let mut bytes1 = Vec::with_capacity(range1.len());
+        for_each_chunk(self.text, range1, |chunk| bytes1.extend_from_slice(chunk));
+        let mut rest = bytes1.as_slice();
+        let mut equal = true;
+        for_each_chunk(self.text, range2, |chunk| {
+            if !equal {
+                return;
+            }
+            let (head, tail) = rest.split_at(chunk.len().min(rest.len()));
+            equal = head == chunk;
+            rest = tail;
+        });
+        equal && rest.is_empty()
+    }
+
+    fn encoding(&self) -> InputEncoding {
+        self.text.encoding()
+    }
+}
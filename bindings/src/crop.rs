@@ -0,0 +1,43 @@
+use crop::{Rope, RopeSlice};
+
+use crate::ChunkedText;
+
+impl<'a> ChunkedText<'a> for RopeSlice<'a> {
+    fn len_bytes(self) -> usize {
+        self.byte_len()
+    }
+
+    fn chunk_at_byte(self, mut byte_idx: usize) -> (&'a [u8], usize) {
+        // crop can't look up the chunk containing an offset directly. Instead the start of the
+        // chunk is found through the last chunk before `byte_idx` and the whole chunk starting
+        // there is returned. Slices must start at a char boundary.
+        while !self.is_char_boundary(byte_idx) {
+            byte_idx -= 1;
+        }
+        let start = match self.byte_slice(..byte_idx).chunks().next_back() {
+            Some(prefix) => byte_idx - prefix.len(),
+            None => byte_idx,
+        };
+        let chunk = self.byte_slice(start..).chunks().next().unwrap_or_default();
+        if start + chunk.len() > byte_idx || byte_idx == self.byte_len() {
+            return (chunk.as_bytes(), start);
+        }
+        // The chunk ends at `byte_idx` so the byte is the start of the next chunk.
+        let chunk = self
+            .byte_slice(byte_idx..)
+            .chunks()
+            .next()
+            .unwrap_or_default();
+        (chunk.as_bytes(), byte_idx)
+    }
+}
+
+impl<'a> ChunkedText<'a> for &'a Rope {
+    fn len_bytes(self) -> usize {
+        self.byte_len()
+    }
+
+    fn chunk_at_byte(self, byte_idx: usize) -> (&'a [u8], usize) {
+        self.byte_slice(..).chunk_at_byte(byte_idx)
+    }
+}
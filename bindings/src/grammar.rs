@@ -1,9 +1,14 @@
+use std::ffi::{c_char, c_void, CStr};
 use std::fmt;
+use std::num::NonZeroU16;
 use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
+use std::slice;
 
 use libloading::{Library, Symbol};
 
+use crate::LookaheadIterator;
+
 /// Lowest supported ABI version of a grammar.
 // WARNING: update when updating vendored c sources
 // `TREE_SITTER_MIN_COMPATIBLE_LANGUAGE_VERSION`
@@ -11,6 +16,12 @@ pub const MIN_COMPATIBLE_ABI_VERSION: u32 = 13;
 // `TREE_SITTER_LANGUAGE_VERSION`
 pub const ABI_VERSION: u32 = 15;
 
+/// The numerical id of a field in a [`Grammar`].
+///
+/// Field ids are interned per grammar: the same name may map to different ids in different
+/// grammars. Zero is used by tree-sitter to signal "no field" so valid ids are never zero.
+pub type FieldId = NonZeroU16;
+
 // opaque pointer
 enum GrammarData {}
 
@@ -29,6 +40,38 @@ impl std::fmt::Debug for Grammar {
     }
 }
 
+/// The `tree_sitter_<name>` function of a grammar which was linked into the binary.
+///
+/// ```no_run
+/// use tree_house_bindings::{Grammar, LanguageFn};
+///
+/// extern "C" {
+///     fn tree_sitter_rust() -> *const std::ffi::c_void;
+/// }
+///
+/// const RUST: LanguageFn = unsafe { LanguageFn::from_raw(tree_sitter_rust) };
+///
+/// let grammar = Grammar::from_language_fn(RUST).unwrap();
+/// ```
+#[derive(Clone, Copy)]
+pub struct LanguageFn(unsafe extern "C" fn() -> *const c_void);
+
+impl LanguageFn {
+    /// # Safety
+    ///
+    /// `language_fn` must be the `tree_sitter_<name>` function generated by tree-sitter
+    /// for a grammar.
+    pub const unsafe fn from_raw(language_fn: unsafe extern "C" fn() -> *const c_void) -> Self {
+        Self(language_fn)
+    }
+}
+
+impl std::fmt::Debug for LanguageFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LanguageFn").finish()
+    }
+}
+
 impl Grammar {
     /// Loads a shared library containing a tree sitter grammar with name `name`
     // from `library_path`.
@@ -53,10 +96,30 @@ impl Grammar {
                 })?;
             Grammar { ptr: language_fn() }
         };
-        let version = grammar.abi_version();
+        grammar.check_abi_version()?;
+        std::mem::forget(library);
+        Ok(grammar)
+    }
+
+    /// Creates a grammar from the `tree_sitter_<name>` function of a grammar which was
+    /// statically linked into the binary. Unlike [`Grammar::new`] this doesn't need to
+    /// load a shared library at runtime.
+    ///
+    /// `skidder` can build static archives of grammars and generate a Rust module with
+    /// the matching [`LanguageFn`]s.
+    pub fn from_language_fn(language_fn: LanguageFn) -> Result<Grammar, Error> {
+        let ptr = unsafe { (language_fn.0)() };
+        let grammar = Grammar {
+            ptr: NonNull::new(ptr as *mut GrammarData).ok_or(Error::NullGrammar)?,
+        };
+        grammar.check_abi_version()?;
+        Ok(grammar)
+    }
+
+    fn check_abi_version(self) -> Result<(), Error> {
+        let version = self.abi_version();
         if (MIN_COMPATIBLE_ABI_VERSION..=ABI_VERSION).contains(&version) {
-            std::mem::forget(library);
-            Ok(grammar)
+            Ok(())
         } else {
             Err(Error::IncompatibleVersion { version })
         }
@@ -66,10 +129,160 @@ impl Grammar {
         unsafe { ts_language_abi_version(self) }
     }
 
+    /// Get the name of this grammar.
+    ///
+    /// Returns `None` for grammars generated with an ABI version older than 15.
+    pub fn name(self) -> Option<&'static str> {
+        unsafe {
+            let ptr = ts_language_name(self);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+        }
+    }
+
+    /// Get the semantic version of this grammar.
+    ///
+    /// Returns `None` for grammars generated with an ABI version older than 15.
+    pub fn version(self) -> Option<SemanticVersion> {
+        unsafe { ts_language_metadata(self).map(|metadata| *metadata.as_ref()) }
+    }
+
     pub fn node_kind_is_visible(self, kind_id: u16) -> bool {
         let symbol_type = unsafe { ts_language_symbol_type(self, kind_id) };
         symbol_type <= (SymbolType::Anonymous as u32)
     }
+
+    /// Check whether the node kind with the given numerical id is *named*.
+    ///
+    /// Named node kinds correspond to named rules in the grammar, whereas
+    /// *anonymous* node kinds correspond to string literals in the grammar.
+    pub fn node_kind_is_named(self, kind_id: u16) -> bool {
+        let symbol_type = unsafe { ts_language_symbol_type(self, kind_id) };
+        symbol_type == (SymbolType::Regular as u32)
+    }
+
+    /// Check whether the node kind with the given numerical id is a supertype.
+    ///
+    /// See [`Grammar::supertypes`].
+    pub fn node_kind_is_supertype(self, kind_id: u16) -> bool {
+        let symbol_type = unsafe { ts_language_symbol_type(self, kind_id) };
+        symbol_type == (SymbolType::Supertype as u32)
+    }
+
+    /// Get the number of distinct node kinds in this grammar.
+    pub fn node_kind_count(self) -> u32 {
+        unsafe { ts_language_symbol_count(self) }
+    }
+
+    /// Get the name of the node kind with the given numerical id.
+    pub fn node_kind_for_id(self, kind_id: u16) -> Option<&'static str> {
+        unsafe {
+            let ptr = ts_language_symbol_name(self, kind_id);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+        }
+    }
+
+    /// Get the numerical id of the visible node kind with the given name.
+    ///
+    /// `named` selects between named node kinds like `identifier` and anonymous
+    /// node kinds like `"fn"` which share the same name.
+    pub fn id_for_node_kind(self, kind: &str, named: bool) -> Option<u16> {
+        let id =
+            unsafe { ts_language_symbol_for_name(self, kind.as_ptr(), kind.len() as u32, named) };
+        (id != 0).then_some(id)
+    }
+
+    /// Get the numerical ids of all supertype node kinds in this grammar.
+    ///
+    /// Supertypes are hidden rules which group other node kinds together, for example
+    /// `_expression` in many grammars. Returns an empty slice for grammars generated with
+    /// an ABI version older than 15.
+    pub fn supertypes(self) -> &'static [u16] {
+        unsafe {
+            let mut len = 0;
+            let ptr = ts_language_supertypes(self, &mut len);
+            raw_symbols(ptr, len)
+        }
+    }
+
+    /// Get the numerical ids of the node kinds that are grouped under the given supertype.
+    ///
+    /// Returns an empty slice if `supertype` is not a supertype of this grammar.
+    pub fn subtypes(self, supertype: u16) -> &'static [u16] {
+        unsafe {
+            let mut len = 0;
+            let ptr = ts_language_subtypes(self, supertype, &mut len);
+            raw_symbols(ptr, len)
+        }
+    }
+
+    /// Get the number of parse states in this grammar.
+    pub fn parse_state_count(self) -> u32 {
+        unsafe { ts_language_state_count(self) }
+    }
+
+    /// Get the next parse state after consuming a node of the given kind in `state`.
+    ///
+    /// Combine this with [`Grammar::lookahead_iterator`] to generate completion
+    /// suggestions or valid symbols in error nodes. Use [`crate::Node::grammar_id`]
+    /// for valid symbols.
+    pub fn next_parse_state(self, state: u16, kind_id: u16) -> u16 {
+        unsafe { ts_language_next_state(self, state, kind_id) }
+    }
+
+    /// Create an iterator over the node kinds which are valid in the given parse state.
+    ///
+    /// Returns `None` if `state` is not a valid parse state of this grammar.
+    /// See [`LookaheadIterator`].
+    pub fn lookahead_iterator(self, state: u16) -> Option<LookaheadIterator> {
+        LookaheadIterator::new(self, state)
+    }
+
+    /// Get the number of distinct field names in this grammar.
+    pub fn field_count(self) -> u32 {
+        unsafe { ts_language_field_count(self) }
+    }
+
+    /// Get the name of the field with the given numerical id.
+    ///
+    /// Returns `None` if the id is out of bounds for this grammar.
+    pub fn field_name_for_id(self, field_id: FieldId) -> Option<&'static str> {
+        unsafe {
+            let ptr = ts_language_field_name_for_id(self, field_id.get());
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+        }
+    }
+
+    /// Get the numerical id of the field with the given name.
+    ///
+    /// Returns `None` if this grammar does not define a field called `name`.
+    pub fn field_id_for_name(self, name: &str) -> Option<FieldId> {
+        let id = unsafe { ts_language_field_id_for_name(self, name.as_ptr(), name.len() as u32) };
+        FieldId::new(id)
+    }
+}
+
+unsafe fn raw_symbols(ptr: *const u16, len: u32) -> &'static [u16] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len as usize)
+    }
+}
+
+/// The semantic version of a [`Grammar`] as declared in its `tree-sitter.json`.
+// `TSLanguageMetadata`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SemanticVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl fmt::Display for SemanticVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -88,6 +301,8 @@ pub enum Error {
     },
     #[error("Tried to load grammar with incompatible ABI {version}.")]
     IncompatibleVersion { version: u32 },
+    #[error("The grammar function returned a null pointer.")]
+    NullGrammar,
 }
 
 /// An error that occurred when trying to assign an incompatible [`Grammar`] to
@@ -128,4 +343,51 @@ extern "C" {
     ///
     /// See also `ts_node_is_named`. Hidden nodes are never returned from the API.
     pub fn ts_language_symbol_type(grammar: Grammar, symbol: u16) -> u32;
+
+    /// Get a node type string for the given numerical id.
+    pub fn ts_language_symbol_name(grammar: Grammar, symbol: u16) -> *const c_char;
+
+    /// Get the number of distinct node types in the language.
+    pub fn ts_language_symbol_count(grammar: Grammar) -> u32;
+
+    /// Get the numerical id for the given node type string.
+    pub fn ts_language_symbol_for_name(
+        grammar: Grammar,
+        name: *const u8,
+        name_len: u32,
+        is_named: bool,
+    ) -> u16;
+
+    /// Get a list of all supertype symbols for the language.
+    pub fn ts_language_supertypes(grammar: Grammar, length: &mut u32) -> *const u16;
+
+    /// Get a list of all subtype symbol ids for a given supertype symbol.
+    ///
+    /// See [`ts_language_supertypes`] for fetching all supertype symbols.
+    pub fn ts_language_subtypes(grammar: Grammar, supertype: u16, length: &mut u32) -> *const u16;
+
+    /// Get the metadata for this language. This information is generated by the
+    /// CLI, and relies on the language author providing the correct metadata in
+    /// the language's `tree-sitter.json` file.
+    pub fn ts_language_metadata(grammar: Grammar) -> Option<NonNull<SemanticVersion>>;
+
+    /// Get the name of this language. This returns `NULL` in older parsers.
+    pub fn ts_language_name(grammar: Grammar) -> *const c_char;
+
+    /// Get the number of valid states in this language.
+    pub fn ts_language_state_count(grammar: Grammar) -> u32;
+
+    /// Get the next parse state. Combine this with lookahead iterators to generate
+    /// completion suggestions or valid symbols in error nodes. Use
+    /// `ts_node_grammar_symbol` for valid symbols.
+    pub fn ts_language_next_state(grammar: Grammar, state: u16, symbol: u16) -> u16;
+
+    /// Get the number of distinct field names in the language.
+    pub fn ts_language_field_count(grammar: Grammar) -> u32;
+
+    /// Get the field name string for the given numerical id.
+    pub fn ts_language_field_name_for_id(grammar: Grammar, id: u16) -> *const c_char;
+
+    /// Get the numerical id for the given field name string.
+    pub fn ts_language_field_id_for_name(grammar: Grammar, name: *const u8, name_len: u32) -> u16;
 }
@@ -1,24 +1,37 @@
+mod chunked;
 mod grammar;
+mod lookahead_iterator;
 mod node;
 mod parser;
 pub mod query;
 mod query_cursor;
 mod tree;
 mod tree_cursor;
+mod utf16;
 
 #[cfg(feature = "ropey")]
 mod ropey;
 #[cfg(feature = "ropey")]
 pub use ropey::RopeInput;
 
+#[cfg(feature = "crop")]
+mod crop;
+
 use std::ops;
 
-pub use grammar::{Grammar, IncompatibleGrammarError};
-pub use node::Node;
-pub use parser::{Parser, ParserInputRaw};
-pub use query::{Capture, Pattern, Query, QueryStr};
-pub use query_cursor::{InactiveQueryCursor, MatchedNode, MatchedNodeIdx, QueryCursor, QueryMatch};
-pub use tree::{InputEdit, Tree};
+pub use chunked::{ChunkedInput, ChunkedText, StringInput};
+pub use grammar::{FieldId, Grammar, IncompatibleGrammarError, LanguageFn, SemanticVersion};
+pub use lookahead_iterator::LookaheadIterator;
+pub use node::{Node, OwnedNode};
+pub use parser::{InputEncoding, LogType, Logger, Parser, ParserInputRaw, ProgressCallback};
+pub use query::{
+    Capture, CaptureQuantifier, Directive, Pattern, PredicateRegistry, Query, QueryStr,
+};
+pub use query_cursor::{
+    Captures, InactiveQueryCursor, InvalidRangeError, MatchedNode, MatchedNodeIdx, QueryCursor,
+    QueryMatch,
+};
+pub use tree::{InputEdit, SyntaxError, SyntaxErrorKind, SyntaxErrors, Tree};
 pub use tree_cursor::TreeCursor;
 
 #[repr(C)]
@@ -49,6 +62,13 @@ pub trait Input {
     type Cursor: regex_cursor::Cursor;
     fn cursor_at(&mut self, offset: u32) -> &mut Self::Cursor;
     fn eq(&mut self, range1: ops::Range<u32>, range2: ops::Range<u32>) -> bool;
+
+    /// The encoding of the bytes yielded by [`Input::cursor_at`].
+    ///
+    /// Text predicates like `#match?` transcode non UTF-8 text before matching.
+    fn encoding(&self) -> InputEncoding {
+        InputEncoding::Utf8
+    }
 }
 
 pub trait IntoInput {
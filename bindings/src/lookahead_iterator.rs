@@ -0,0 +1,134 @@
+use std::ffi::{c_char, CStr};
+use std::ptr::NonNull;
+
+use crate::Grammar;
+
+// opaque pointer
+enum LookaheadIteratorData {}
+
+/// An iterator over the kinds of the nodes that are valid in a given parse state.
+///
+/// Lookahead iterators can be used to generate completion suggestions and to improve syntax
+/// error diagnostics. To get the symbols that are valid in an `ERROR` node use the parse state
+/// of its first leaf node (see [`crate::Node::parse_state`]). For `MISSING` nodes, a lookahead
+/// iterator created with the [`next_parse_state`](crate::Node::next_parse_state) of the
+/// previous non-extra leaf node may be appropriate.
+///
+/// The iterator yields the numerical ids of all valid symbols including hidden and anonymous
+/// ones. Use [`Grammar::node_kind_is_visible`] or [`Grammar::node_kind_is_named`] to filter
+/// them.
+pub struct LookaheadIterator {
+    ptr: NonNull<LookaheadIteratorData>,
+}
+
+unsafe impl Send for LookaheadIterator {}
+unsafe impl Sync for LookaheadIterator {}
+
+impl LookaheadIterator {
+    /// Create a new lookahead iterator for the given grammar and parse state.
+    ///
+    /// Returns `None` if the state is invalid for the grammar.
+    pub fn new(grammar: Grammar, state: u16) -> Option<Self> {
+        let ptr = unsafe { ts_lookahead_iterator_new(grammar, state) }?;
+        Some(LookaheadIterator { ptr })
+    }
+
+    /// Get the grammar that this iterator is currently using.
+    pub fn grammar(&self) -> Grammar {
+        unsafe { ts_lookahead_iterator_language(self.ptr) }
+    }
+
+    /// Get the numerical id of the current symbol.
+    ///
+    /// Before [`Iterator::next`] is called for the first time this is the id of the `ERROR`
+    /// symbol (`u16::MAX`).
+    pub fn current_symbol(&self) -> u16 {
+        unsafe { ts_lookahead_iterator_current_symbol(self.ptr) }
+    }
+
+    /// Get the name of the current symbol.
+    pub fn current_symbol_name(&self) -> &'static str {
+        unsafe {
+            CStr::from_ptr(ts_lookahead_iterator_current_symbol_name(self.ptr))
+                .to_str()
+                .unwrap()
+        }
+    }
+
+    /// Reset the iterator to another parse state of the same grammar.
+    ///
+    /// Returns `false` (and leaves the iterator unchanged) if the state is invalid.
+    pub fn reset_state(&mut self, state: u16) -> bool {
+        unsafe { ts_lookahead_iterator_reset_state(self.ptr, state) }
+    }
+
+    /// Reset the iterator to a parse state of another grammar.
+    ///
+    /// Returns `false` (and leaves the iterator unchanged) if the state is invalid for the
+    /// grammar.
+    pub fn reset(&mut self, grammar: Grammar, state: u16) -> bool {
+        unsafe { ts_lookahead_iterator_reset(self.ptr, grammar, state) }
+    }
+
+    /// Iterate over the names of the remaining valid symbols.
+    pub fn names(&mut self) -> impl Iterator<Item = &'static str> + '_ {
+        std::iter::from_fn(move || self.next().map(|_| self.current_symbol_name()))
+    }
+}
+
+impl Iterator for LookaheadIterator {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        unsafe {
+            ts_lookahead_iterator_next(self.ptr)
+                .then(|| ts_lookahead_iterator_current_symbol(self.ptr))
+        }
+    }
+}
+
+impl Drop for LookaheadIterator {
+    fn drop(&mut self) {
+        unsafe { ts_lookahead_iterator_delete(self.ptr) }
+    }
+}
+
+extern "C" {
+    /// Create a new lookahead iterator for the given language and parse state.
+    ///
+    /// This returns `NULL` if state is invalid for the language.
+    fn ts_lookahead_iterator_new(
+        grammar: Grammar,
+        state: u16,
+    ) -> Option<NonNull<LookaheadIteratorData>>;
+    /// Delete a lookahead iterator freeing all the memory used.
+    fn ts_lookahead_iterator_delete(self_: NonNull<LookaheadIteratorData>);
+    /// Reset the lookahead iterator to another state.
+    ///
+    /// This returns `true` if the iterator was reset to the given state and `false`
+    /// otherwise.
+    fn ts_lookahead_iterator_reset_state(self_: NonNull<LookaheadIteratorData>, state: u16)
+        -> bool;
+    /// Reset the lookahead iterator.
+    ///
+    /// This returns `true` if the language was set successfully and `false`
+    /// otherwise.
+    fn ts_lookahead_iterator_reset(
+        self_: NonNull<LookaheadIteratorData>,
+        grammar: Grammar,
+        state: u16,
+    ) -> bool;
+    /// Get the current language of the lookahead iterator.
+    fn ts_lookahead_iterator_language(self_: NonNull<LookaheadIteratorData>) -> Grammar;
+    /// Advance the lookahead iterator to the next symbol.
+    ///
+    /// This returns `true` if there is a new symbol and `false` otherwise.
+    fn ts_lookahead_iterator_next(self_: NonNull<LookaheadIteratorData>) -> bool;
+    /// Get the current symbol of the lookahead iterator.
+    fn ts_lookahead_iterator_current_symbol(self_: NonNull<LookaheadIteratorData>) -> u16;
+    /// Get the current symbol type of the lookahead iterator as a null terminated
+    /// string.
+    fn ts_lookahead_iterator_current_symbol_name(
+        self_: NonNull<LookaheadIteratorData>,
+    ) -> *const c_char;
+}
@@ -1,12 +1,14 @@
 use std::ffi::{c_char, c_void, CStr};
-use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::Range;
 use std::ptr::NonNull;
+use std::sync::Arc;
+use std::{fmt, iter};
 
-use crate::tree::Tree;
+use crate::tree::{SyntaxTreeData, Tree};
 use crate::tree_cursor::TreeCursor;
-use crate::Grammar;
+use crate::{FieldId, Grammar, LookaheadIterator, Point};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -80,6 +82,16 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_symbol(self.as_raw()) }
     }
 
+    /// Get this node's type as a numerical id as it appears in the grammar,
+    /// ignoring aliases.
+    ///
+    /// This should be used in [`Grammar::next_parse_state`] instead of
+    /// [`Node::kind_id`].
+    #[inline]
+    pub fn grammar_id(&self) -> u16 {
+        unsafe { ts_node_grammar_symbol(self.as_raw()) }
+    }
+
     /// Get the [`Grammar`] that was used to parse this node's syntax tree.
     #[inline]
     pub fn grammar(&self) -> Grammar {
@@ -104,6 +116,54 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_is_missing(self.as_raw()) }
     }
 
+    /// Check if this node is *extra*.
+    ///
+    /// Extra nodes represent things like comments, which are not required
+    /// by the grammar, but can appear anywhere.
+    #[inline]
+    pub fn is_extra(&self) -> bool {
+        unsafe { ts_node_is_extra(self.as_raw()) }
+    }
+
+    /// Check if this node is a syntax error.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        unsafe { ts_node_is_error(self.as_raw()) }
+    }
+
+    /// Check if this node is a syntax error or contains any syntax errors.
+    #[inline]
+    pub fn has_error(&self) -> bool {
+        unsafe { ts_node_has_error(self.as_raw()) }
+    }
+
+    /// Check if this node has been edited.
+    #[inline]
+    pub fn has_changes(&self) -> bool {
+        unsafe { ts_node_has_changes(self.as_raw()) }
+    }
+
+    /// Get this node's parse state.
+    #[inline]
+    pub fn parse_state(&self) -> u16 {
+        unsafe { ts_node_parse_state(self.as_raw()) }
+    }
+
+    /// Get the parse state after this node.
+    #[inline]
+    pub fn next_parse_state(&self) -> u16 {
+        unsafe { ts_node_next_parse_state(self.as_raw()) }
+    }
+
+    /// Create an iterator over the node kinds which are valid in this node's
+    /// parse state. See [`crate::LookaheadIterator`].
+    ///
+    /// For `ERROR` nodes the parse state of the error itself is not useful.
+    /// Use the parse state of the first leaf within the error instead.
+    pub fn lookahead_iterator(&self) -> Option<LookaheadIterator> {
+        LookaheadIterator::new(self.grammar(), self.parse_state())
+    }
+
     /// Get the byte offsets where this node starts.
     #[inline(always)]
     pub fn start_byte(&self) -> u32 {
@@ -134,6 +194,30 @@ impl<'tree> Node<'tree> {
         self.start_byte()..self.end_byte()
     }
 
+    /// Get the row and column where this node starts.
+    #[inline]
+    pub fn start_point(&self) -> Point {
+        unsafe { ts_node_start_point(self.as_raw()) }
+    }
+
+    /// Get the row and column where this node ends.
+    #[inline]
+    pub fn end_point(&self) -> Point {
+        unsafe { ts_node_end_point(self.as_raw()) }
+    }
+
+    /// Get the range of source code that this node represents, both in terms
+    /// of raw bytes and of row/column coordinates.
+    #[inline]
+    pub fn range(&self) -> crate::Range {
+        crate::Range {
+            start_point: self.start_point(),
+            end_point: self.end_point(),
+            start_byte: self.start_byte(),
+            end_byte: self.end_byte(),
+        }
+    }
+
     /// Get the node's child at the given index, where zero represents the first
     /// child.
     ///
@@ -170,6 +254,48 @@ impl<'tree> Node<'tree> {
         unsafe { ts_node_named_child_count(self.as_raw()) }
     }
 
+    /// Get the first child with the given field name.
+    ///
+    /// If multiple children may have the same field name, access them using
+    /// [`Node::children_by_field_name`].
+    #[inline]
+    pub fn child_by_field_name(&self, field_name: &str) -> Option<Node<'tree>> {
+        unsafe {
+            Node::from_raw(ts_node_child_by_field_name(
+                self.as_raw(),
+                field_name.as_ptr(),
+                field_name.len() as u32,
+            ))
+        }
+    }
+
+    /// Get this node's child with the given numerical field id.
+    ///
+    /// See also [`Node::child_by_field_name`]. You can convert a field name to
+    /// an id using [`Grammar::field_id_for_name`].
+    #[inline]
+    pub fn child_by_field_id(&self, field_id: FieldId) -> Option<Node<'tree>> {
+        unsafe { Node::from_raw(ts_node_child_by_field_id(self.as_raw(), field_id.get())) }
+    }
+
+    /// Get the field name of this node's child at the given index.
+    #[inline]
+    pub fn field_name_for_child(&self, i: u32) -> Option<&'tree str> {
+        unsafe {
+            let ptr = ts_node_field_name_for_child(self.as_raw(), i);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+        }
+    }
+
+    /// Get the field name of this node's *named* child at the given index.
+    #[inline]
+    pub fn field_name_for_named_child(&self, i: u32) -> Option<&'tree str> {
+        unsafe {
+            let ptr = ts_node_field_name_for_named_child(self.as_raw(), i);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+        }
+    }
+
     #[inline]
     unsafe fn map(&self, f: unsafe extern "C" fn(NodeRaw) -> NodeRaw) -> Option<Node<'tree>> {
         Node::from_raw(f(self.as_raw()))
@@ -223,6 +349,32 @@ impl<'tree> Node<'tree> {
         }
     }
 
+    /// Get the smallest node within this node that spans the given range of
+    /// (row, column) positions.
+    #[inline]
+    pub fn descendant_for_point_range(&self, start: Point, end: Point) -> Option<Self> {
+        unsafe {
+            Self::from_raw(ts_node_descendant_for_point_range(
+                self.as_raw(),
+                start,
+                end,
+            ))
+        }
+    }
+
+    /// Get the smallest named node within this node that spans the given range
+    /// of (row, column) positions.
+    #[inline]
+    pub fn named_descendant_for_point_range(&self, start: Point, end: Point) -> Option<Self> {
+        unsafe {
+            Self::from_raw(ts_node_named_descendant_for_point_range(
+                self.as_raw(),
+                start,
+                end,
+            ))
+        }
+    }
+
     /// Iterate over this node's children.
     ///
     /// A [`TreeCursor`] is used to retrieve the children efficiently. Obtain
@@ -242,9 +394,68 @@ impl<'tree> Node<'tree> {
         })
     }
 
+    /// Iterate over this node's children with the given field name.
+    ///
+    /// See also [`Node::children_by_field_id`].
+    pub fn children_by_field_name(&self, field_name: &str) -> impl Iterator<Item = Node<'tree>> {
+        let field_id = self.grammar().field_id_for_name(field_name);
+        let mut cursor = TreeCursor::new(self);
+        let mut done = field_id.is_none() || !cursor.goto_first_child();
+        iter::from_fn(move || {
+            while !done {
+                let node = (cursor.field_id() == field_id).then(|| cursor.node());
+                done = !cursor.goto_next_sibling();
+                if node.is_some() {
+                    return node;
+                }
+            }
+            None
+        })
+    }
+
+    /// Iterate over this node's children with the given numerical field id.
+    ///
+    /// See also [`Node::children_by_field_name`].
+    pub fn children_by_field_id(&self, field_id: FieldId) -> impl Iterator<Item = Node<'tree>> {
+        let mut cursor = TreeCursor::new(self);
+        let mut done = !cursor.goto_first_child();
+        iter::from_fn(move || {
+            while !done {
+                let node = (cursor.field_id() == Some(field_id)).then(|| cursor.node());
+                done = !cursor.goto_next_sibling();
+                if node.is_some() {
+                    return node;
+                }
+            }
+            None
+        })
+    }
+
     pub fn walk(&self) -> TreeCursor<'tree> {
         TreeCursor::new(self)
     }
+
+    /// Create an [`OwnedNode`] for this node which keeps a (cheap, shallow) copy of the
+    /// syntax tree alive.
+    ///
+    /// Every call copies the tree. To create handles for many nodes of the same tree use
+    /// [`OwnedNode::new`] with a shared `Arc<Tree>` instead.
+    pub fn to_owned_node(&self) -> OwnedNode {
+        let tree = Arc::new(unsafe { Tree::copy_from_raw(self.tree.cast()) });
+        // A copy shares all nodes with the original tree except for the root which is
+        // stored in the tree itself.
+        let root = unsafe { Node::from_raw(ts_tree_root_node(self.tree.cast())).unwrap() };
+        let id = if self.id == root.id {
+            tree.root_node().id
+        } else {
+            self.id
+        };
+        OwnedNode {
+            context: self.context,
+            id,
+            tree,
+        }
+    }
 }
 
 impl PartialEq for Node<'_> {
@@ -258,13 +469,125 @@ impl Eq for Node<'_> {}
 unsafe impl Send for Node<'_> {}
 unsafe impl Sync for Node<'_> {}
 
+/// A handle to a syntax node which owns a reference to its [`Tree`] instead of borrowing it.
+///
+/// Unlike [`Node`] it can be stored in long-lived state, sent to other threads and kept
+/// after the tree it was created from was edited or replaced. The full [`Node`] API is
+/// available through [`OwnedNode::node`]. Use [`OwnedNode::resolve`] to find the
+/// corresponding node in a newer tree.
+#[derive(Clone)]
+pub struct OwnedNode {
+    tree: Arc<Tree>,
+    context: [u32; 4],
+    id: NonNull<c_void>,
+}
+
+impl OwnedNode {
+    /// Create a handle for `node` which must be a node of `tree` itself, for example one
+    /// found by navigating from `tree.root_node()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `node` belongs to a different tree, including a copy of `tree`.
+    pub fn new(tree: Arc<Tree>, node: &Node<'_>) -> OwnedNode {
+        assert!(
+            node.tree == tree.as_raw().cast(),
+            "node does not belong to the tree"
+        );
+        OwnedNode {
+            context: node.context,
+            id: node.id,
+            tree,
+        }
+    }
+
+    /// The node this handle refers to.
+    #[inline]
+    pub fn node(&self) -> Node<'_> {
+        Node {
+            context: self.context,
+            id: self.id,
+            tree: self.tree.as_raw().cast(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// The tree the node belongs to.
+    #[inline]
+    pub fn tree(&self) -> &Arc<Tree> {
+        &self.tree
+    }
+
+    /// Find the node with the same byte range and kind in `tree`, usually a tree that
+    /// was parsed after the one this node belongs to.
+    ///
+    /// This only finds the node if the text before it was not changed. Otherwise
+    /// [`OwnedNode::resolve_at`] should be used with the range of the node mapped through
+    /// the edits.
+    pub fn resolve<'tree>(&self, tree: &'tree Tree) -> Option<Node<'tree>> {
+        self.resolve_at(tree, self.node().byte_range())
+    }
+
+    /// Find the node with the same kind as this node which spans exactly `byte_range` in
+    /// `tree`. If multiple nested nodes of that kind span the range, the innermost one is
+    /// returned.
+    pub fn resolve_at<'tree>(
+        &self,
+        tree: &'tree Tree,
+        byte_range: Range<u32>,
+    ) -> Option<Node<'tree>> {
+        let kind = self.node().kind();
+        let mut node = tree
+            .root_node()
+            .descendant_for_byte_range(byte_range.start, byte_range.end)?;
+        while node.byte_range() == byte_range {
+            if node.kind() == kind {
+                return Some(node);
+            }
+            node = node.parent()?;
+        }
+        None
+    }
+}
+
+impl fmt::Debug for OwnedNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.node().fmt(f)
+    }
+}
+
+impl PartialEq for OwnedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for OwnedNode {}
+
+impl Hash for OwnedNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+unsafe impl Send for OwnedNode {}
+unsafe impl Sync for OwnedNode {}
+
 extern "C" {
+    /// Get the root node of the syntax tree.
+    fn ts_tree_root_node(tree: NonNull<SyntaxTreeData>) -> NodeRaw;
+
     /// Get the node's type as a null-terminated string.
     fn ts_node_type(node: NodeRaw) -> *const c_char;
 
     /// Get the node's type as a numerical id.
     fn ts_node_symbol(node: NodeRaw) -> u16;
 
+    /// Get the node's type as a numerical id as it appears in the grammar
+    /// ignoring aliases. This should be used in `ts_language_next_state`
+    /// instead of `ts_node_symbol`.
+    fn ts_node_grammar_symbol(node: NodeRaw) -> u16;
+
     /// Get the node's language.
     fn ts_node_language(node: NodeRaw) -> Grammar;
 
@@ -277,6 +600,25 @@ extern "C" {
     /// in order to recover from certain kinds of syntax errors
     fn ts_node_is_missing(node: NodeRaw) -> bool;
 
+    /// Check if the node is *extra*. Extra nodes represent things like comments,
+    /// which are not required the grammar, but can appear anywhere
+    fn ts_node_is_extra(node: NodeRaw) -> bool;
+
+    /// Check if the node is a syntax error
+    fn ts_node_is_error(node: NodeRaw) -> bool;
+
+    /// Check if the node is a syntax error or contains any syntax errors
+    fn ts_node_has_error(node: NodeRaw) -> bool;
+
+    /// Check if a syntax node has been edited
+    fn ts_node_has_changes(node: NodeRaw) -> bool;
+
+    /// Get this node's parse state
+    fn ts_node_parse_state(node: NodeRaw) -> u16;
+
+    /// Get the parse state after this node
+    fn ts_node_next_parse_state(node: NodeRaw) -> u16;
+
     /// Get the node's immediate parent
     fn ts_node_parent(node: NodeRaw) -> NodeRaw;
 
@@ -294,6 +636,23 @@ extern "C" {
     /// Get the node's number of *named* children. See also [`ts_node_is_named`]
     fn ts_node_named_child_count(node: NodeRaw) -> u32;
 
+    /// Get the field name for node's child at the given index, where zero represents
+    /// the first child. Returns NULL, if no field is found.
+    fn ts_node_field_name_for_child(node: NodeRaw, child_index: u32) -> *const c_char;
+
+    /// Get the field name for node's named child at the given index, where zero
+    /// represents the first named child. Returns NULL, if no field is found.
+    fn ts_node_field_name_for_named_child(node: NodeRaw, named_child_index: u32) -> *const c_char;
+
+    /// Get the node's child with the given field name.
+    fn ts_node_child_by_field_name(node: NodeRaw, name: *const u8, name_length: u32) -> NodeRaw;
+
+    /// Get the node's child with the given numerical field id.
+    ///
+    /// You can convert a field name to an id using the
+    /// `ts_language_field_id_for_name` function.
+    fn ts_node_child_by_field_id(node: NodeRaw, field_id: u16) -> NodeRaw;
+
     /// Get the node's next sibling
     fn ts_node_next_sibling(node: NodeRaw) -> NodeRaw;
 
@@ -312,6 +671,21 @@ extern "C" {
     /// of bytes or (row, column) positions
     fn ts_node_named_descendant_for_byte_range(node: NodeRaw, start: u32, end: u32) -> NodeRaw;
 
+    /// Get the smallest node within this node that spans the given range of
+    /// (row, column) positions
+    fn ts_node_descendant_for_point_range(node: NodeRaw, start: Point, end: Point) -> NodeRaw;
+
+    /// Get the smallest named node within this node that spans the given range
+    /// of (row, column) positions
+    fn ts_node_named_descendant_for_point_range(node: NodeRaw, start: Point, end: Point)
+        -> NodeRaw;
+
     /// Get the node's end byte.
     fn ts_node_end_byte(node: NodeRaw) -> u32;
+
+    /// Get the node's start position in terms of rows and columns.
+    fn ts_node_start_point(node: NodeRaw) -> Point;
+
+    /// Get the node's end position in terms of rows and columns.
+    fn ts_node_end_point(node: NodeRaw) -> Point;
 }
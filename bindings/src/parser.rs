@@ -1,7 +1,12 @@
 use std::cell::Cell;
+use std::ffi::{c_char, c_int, CStr};
+#[cfg(unix)]
+use std::os::fd::{IntoRawFd, OwnedFd};
 use std::os::raw::c_void;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{fmt, mem, ptr};
 
@@ -29,11 +34,33 @@ impl Drop for RawParser {
     }
 }
 
+/// A callback that is invoked periodically during parsing or query execution with
+/// the byte offset that has been reached. Returning `true` cancels the parse or query.
+///
+/// The callback is shared so that the same callback can be passed to multiple parsers
+/// or query cursors, for example one for each injection layer.
+pub type ProgressCallback = Arc<dyn Fn(u32) -> bool + Send + Sync>;
+
+/// The kind of a message passed to a [`Logger`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogType {
+    Parse,
+    Lex,
+}
+
+/// A callback that receives the log messages emitted by the lexer and parser.
+/// See [`Parser::set_logger`].
+pub type Logger = Box<dyn FnMut(LogType, &str) + Send>;
+
 /// A stateful object that this is used to produce a [`Tree`] based on some
 /// source code.
 pub struct Parser {
     ptr: NonNull<ParserData>,
     timeout: Option<Duration>,
+    cancellation_flag: Option<Arc<AtomicBool>>,
+    progress_callback: Option<ProgressCallback>,
+    // Double boxed so that the C library can hold onto a thin pointer.
+    logger: Option<Box<Logger>>,
 }
 
 impl Parser {
@@ -48,7 +75,13 @@ impl Parser {
             }
             None => unsafe { ts_parser_new() },
         };
-        Parser { ptr, timeout: None }
+        Parser {
+            ptr,
+            timeout: None,
+            cancellation_flag: None,
+            progress_callback: None,
+            logger: None,
+        }
     }
 
     /// Set the language that the parser should use for parsing.
@@ -62,10 +95,84 @@ impl Parser {
         }
     }
 
+    /// Set the maximum duration that parsing should be allowed to take before
+    /// halting. If parsing takes longer than this, [`Parser::parse`] returns
+    /// `None` and the next parse resumes where it stopped (see [`Parser::reset`]).
     pub fn set_timeout(&mut self, duration: Duration) {
         self.timeout = Some(duration);
     }
 
+    /// Remove the timeout set with [`Parser::set_timeout`].
+    pub fn clear_timeout(&mut self) {
+        self.timeout = None;
+    }
+
+    /// Set a flag which cancels parsing when it is set to `true`.
+    ///
+    /// The flag is checked periodically while parsing so another thread can
+    /// stop a long running parse early, for example because the document was
+    /// edited again. A cancelled parse returns `None` and the next parse resumes
+    /// where it stopped (see [`Parser::reset`]). Note that the flag is not reset
+    /// by the parser.
+    pub fn set_cancellation_flag(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.cancellation_flag = flag;
+    }
+
+    /// Set a callback which is invoked periodically while parsing with the
+    /// current byte offset of the parser. Parsing is cancelled (and
+    /// [`Parser::parse`] returns `None`) as soon as the callback returns `true`.
+    /// Like a timeout, the next parse resumes the cancelled one (see
+    /// [`Parser::reset`]).
+    pub fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.progress_callback = callback;
+    }
+
+    /// Set a callback which receives the log messages emitted by the lexer and
+    /// parser while parsing. Passing `None` removes the current logger.
+    ///
+    /// This is mostly useful to debug grammars.
+    pub fn set_logger(&mut self, logger: Option<Logger>) {
+        unsafe extern "C" fn log(payload: *mut c_void, log_type: u32, message: *const c_char) {
+            let logger: &mut Logger = &mut *payload.cast();
+            let log_type = if log_type == 0 {
+                LogType::Parse
+            } else {
+                LogType::Lex
+            };
+            if let Ok(message) = CStr::from_ptr(message).to_str() {
+                // Unwinding across the FFI boundary is UB so panics are swallowed.
+                let _ = catch_unwind(AssertUnwindSafe(|| logger(log_type, message)));
+            }
+        }
+
+        let mut logger = logger.map(Box::new);
+        let raw = match &mut logger {
+            Some(logger) => LoggerRaw {
+                payload: ptr::from_mut::<Logger>(logger).cast(),
+                log: Some(log),
+            },
+            None => LoggerRaw {
+                payload: ptr::null_mut(),
+                log: None,
+            },
+        };
+        unsafe { ts_parser_set_logger(self.ptr, raw) };
+        // The old logger must only be dropped once the parser no longer references it.
+        self.logger = logger;
+    }
+
+    /// Write debugging graphs in the DOT language to the given file descriptor
+    /// while parsing. Passing `None` stops writing graphs.
+    ///
+    /// The graphs can be piped to a `dot(1)` process in order to generate
+    /// SVG output. The parser takes ownership of the file descriptor and closes
+    /// it once graphs are disabled or the parser is dropped.
+    #[cfg(unix)]
+    pub fn print_dot_graphs(&mut self, fd: Option<OwnedFd>) {
+        let fd = fd.map_or(-1, |fd| fd.into_raw_fd());
+        unsafe { ts_parser_print_dot_graphs(self.ptr, fd) }
+    }
+
     /// Set the ranges of text that the parser should include when parsing. By default, the parser
     /// will always include entire documents. This function allows you to parse only a *portion*
     /// of a document but still return a syntax tree whose ranges match up with the document as a
@@ -87,6 +194,23 @@ impl Parser {
         }
     }
 
+    /// Instruct the parser to start the next parse from the beginning.
+    ///
+    /// A parse which was stopped by a timeout, the cancellation flag or the progress
+    /// callback is resumed where it left off by the next call to [`Parser::parse`], which
+    /// must then pass the same input and old tree. Call this first to parse other input
+    /// instead. Setting the grammar also resets the parser.
+    #[doc(alias = "ts_parser_reset")]
+    pub fn reset(&mut self) {
+        unsafe { ts_parser_reset(self.ptr) }
+    }
+
+    /// Parse `input`, reusing the unchanged parts of `old_tree` which must have been
+    /// edited to match the input (see [`Tree::edit`]).
+    ///
+    /// Returns `None` if no grammar was set or if the parse was stopped by the timeout,
+    /// the cancellation flag or the progress callback. A stopped parse is resumed by the
+    /// next call unless [`Parser::reset`] is called first.
     #[must_use]
     pub fn parse<I: Input>(
         &mut self,
@@ -119,23 +243,62 @@ impl Parser {
                 }
             }
         }
+        let encoding = input.encoding();
+        assert!(
+            !matches!(encoding, InputEncoding::Custom),
+            "custom input encodings are not supported"
+        );
         let input = ParserInputRaw {
             payload: NonNull::from(&mut input).cast(),
             read: read::<I>,
-            encoding: InputEncoding::Utf8,
+            encoding,
             decode: None,
         };
 
-        let options = if let Some(timeout) = self.timeout {
-            unsafe extern "C" fn check_timeout(state: NonNull<ParseState>) -> bool {
-                let &(start, timeout) = state.as_ref().payload.cast().as_ref();
-                Instant::now().duration_since(start) > timeout
+        struct Progress<'a> {
+            deadline: Option<Instant>,
+            cancellation_flag: Option<&'a AtomicBool>,
+            callback: Option<&'a ProgressCallback>,
+        }
+
+        unsafe extern "C" fn check_progress(state: NonNull<ParseState>) -> bool {
+            let state = state.as_ref();
+            let progress: &mut Progress = state.payload.cast().as_mut();
+            if progress
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+            {
+                return true;
+            }
+            if progress
+                .cancellation_flag
+                .is_some_and(|flag| flag.load(Ordering::Relaxed))
+            {
+                return true;
+            }
+            match &progress.callback {
+                // Unwinding across the FFI boundary is UB so a panicking
+                // callback cancels the parse instead.
+                Some(callback) => {
+                    catch_unwind(AssertUnwindSafe(|| callback(state.current_byte_offset)))
+                        .unwrap_or(true)
+                }
+                None => false,
             }
+        }
 
-            let start = Instant::now();
+        let mut progress = Progress {
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            cancellation_flag: self.cancellation_flag.as_deref(),
+            callback: self.progress_callback.as_ref(),
+        };
+        let options = if progress.deadline.is_some()
+            || progress.cancellation_flag.is_some()
+            || progress.callback.is_some()
+        {
             ParseOptions {
-                payload: Some(NonNull::from(&mut (start, timeout)).cast()),
-                progress_callback: Some(check_timeout),
+                payload: Some(NonNull::from(&mut progress).cast()),
+                progress_callback: Some(check_progress),
             }
         } else {
             ParseOptions::default()
@@ -160,6 +323,14 @@ unsafe impl Send for Parser {}
 
 impl Drop for Parser {
     fn drop(&mut self) {
+        // The parser is reused so reset all debugging state and any stopped parse which
+        // would otherwise leak into the next parser taken from the cache.
+        if self.logger.is_some() {
+            self.set_logger(None);
+        }
+        #[cfg(unix)]
+        self.print_dot_graphs(None);
+        self.reset();
         PARSER_CACHE.set(Some(RawParser { ptr: self.ptr }));
     }
 }
@@ -201,15 +372,29 @@ pub struct ParserInputRaw {
 }
 
 // `TSInputEncoding`
+/// The encoding of the text provided by an [`Input`].
+///
+/// Byte offsets (for example [`crate::Node::byte_range`]) always count bytes in
+/// this encoding, so for UTF-16 text they are twice the code unit index.
 #[repr(u32)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InputEncoding {
     Utf8,
     Utf16LE,
     Utf16BE,
+    /// Only supported by the C library: [`Input`] implementations must not use this encoding.
     Custom,
 }
 
+impl InputEncoding {
+    /// The UTF-16 encoding with the endianness of the current target.
+    pub const UTF16_NATIVE: InputEncoding = if cfg!(target_endian = "little") {
+        InputEncoding::Utf16LE
+    } else {
+        InputEncoding::Utf16BE
+    };
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct ParseState {
@@ -221,13 +406,20 @@ struct ParseState {
 
 /// A function that accepts the current parser state and returns `true` when the parse should be
 /// cancelled.
-type ProgressCallback = unsafe extern "C" fn(state: NonNull<ParseState>) -> bool;
+type RawProgressCallback = unsafe extern "C" fn(state: NonNull<ParseState>) -> bool;
 
 #[repr(C)]
 #[derive(Debug, Default)]
 struct ParseOptions {
     payload: Option<NonNull<c_void>>,
-    progress_callback: Option<ProgressCallback>,
+    progress_callback: Option<RawProgressCallback>,
+}
+
+// `TSLogger`
+#[repr(C)]
+struct LoggerRaw {
+    payload: *mut c_void,
+    log: Option<unsafe extern "C" fn(payload: *mut c_void, log_type: u32, buffer: *const c_char)>,
 }
 
 extern "C" {
@@ -260,6 +452,22 @@ extern "C" {
         count: u32,
     ) -> bool;
 
+    /// Set the logger that a parser should use during parsing. The parser does not take
+    /// ownership over the logger payload. If a logger was previously assigned, the caller is
+    /// responsible for releasing any memory owned by the previous logger.
+    fn ts_parser_set_logger(parser: NonNull<ParserData>, logger: LoggerRaw);
+    /// Set the file descriptor to which the parser should write debugging graphs during
+    /// parsing. The graphs are formatted in the DOT language. You may want to pipe these
+    /// graphs directly to a `dot(1)` process in order to generate SVG output. You can turn off
+    /// this logging by passing a negative number.
+    fn ts_parser_print_dot_graphs(parser: NonNull<ParserData>, fd: c_int);
+    /// Instruct the parser to start the next parse from the beginning. If the parser
+    /// previously failed because of a timeout or a cancellation, then by default, it will
+    /// resume where it left off on the next call to `ts_parser_parse` or other parsing
+    /// functions. If you don't want to resume, and instead intend to use this parser to
+    /// parse some other document, you must call `ts_parser_reset` first.
+    fn ts_parser_reset(parser: NonNull<ParserData>);
+
     /*
     fn ts_parser_parse(
         parser: NonNull<ParserData>,
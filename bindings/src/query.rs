@@ -1,12 +1,18 @@
+use std::borrow::Cow;
 use std::fmt::{self, Display};
 use std::ops::Range;
 use std::ptr::NonNull;
 use std::{slice, str};
 
-use crate::query::predicate::TextPredicate;
-pub use crate::query::predicate::{InvalidPredicateError, Predicate};
+use crate::query::predicate::{CustomPredicate, TextPredicate};
+pub use crate::query::predicate::{
+    InvalidPredicateError, Predicate, PredicateInput, PredicateRegistry,
+};
 use crate::Grammar;
 
+pub use crate::query::directive::Directive;
+
+mod directive;
 mod predicate;
 mod property;
 
@@ -59,6 +65,8 @@ pub enum QueryData {}
 #[derive(Debug)]
 pub(super) struct PatternData {
     text_predicates: Range<u32>,
+    custom_predicates: Range<u32>,
+    directives: Range<u32>,
 }
 
 #[derive(Debug)]
@@ -67,6 +75,8 @@ pub struct Query {
     num_captures: u32,
     num_strings: u32,
     text_predicates: Vec<TextPredicate>,
+    custom_predicates: Vec<CustomPredicate>,
+    directives: Vec<Directive>,
     patterns: Box<[PatternData]>,
 }
 
@@ -83,6 +93,24 @@ impl Query {
     pub fn new(
         grammar: Grammar,
         source: &str,
+        custom_predicate: impl FnMut(Pattern, UserPredicate) -> Result<(), InvalidPredicateError>,
+    ) -> Result<Self, ParseError> {
+        Self::with_predicates(
+            grammar,
+            source,
+            &PredicateRegistry::default(),
+            custom_predicate,
+        )
+    }
+
+    /// Create a new query like [`Query::new`] which additionally evaluates the
+    /// predicates registered in `registry` for every match.
+    ///
+    /// Registered predicates are not passed to `custom_predicate`.
+    pub fn with_predicates(
+        grammar: Grammar,
+        source: &str,
+        registry: &PredicateRegistry,
         mut custom_predicate: impl FnMut(Pattern, UserPredicate) -> Result<(), InvalidPredicateError>,
     ) -> Result<Self, ParseError> {
         assert!(
@@ -160,12 +188,14 @@ impl Query {
             num_captures,
             num_strings,
             text_predicates: Vec::new(),
+            custom_predicates: Vec::new(),
+            directives: Vec::new(),
             patterns: Box::default(),
         };
         let patterns: Result<_, ParseError> = (0..num_patterns)
             .map(|pattern| {
                 query
-                    .parse_pattern_predicates(Pattern(pattern), &mut custom_predicate)
+                    .parse_pattern_predicates(Pattern(pattern), registry, &mut custom_predicate)
                     .map_err(|err| {
                         let pattern_start =
                             unsafe { ts_query_start_byte_for_pattern(query.raw, pattern) as usize };
@@ -272,17 +302,143 @@ impl Query {
         &self.text_predicates[range.start as usize..range.end as usize]
     }
 
+    pub(crate) fn pattern_custom_predicates(&self, pattern_idx: u16) -> &[CustomPredicate] {
+        let range = self.patterns[pattern_idx as usize]
+            .custom_predicates
+            .clone();
+        &self.custom_predicates[range.start as usize..range.end as usize]
+    }
+
+    /// Get the directives (`#offset!`, `#trim!`, `#gsub!` and `#strip!`) of the
+    /// given pattern in the order they appear in the query's source.
+    #[must_use]
+    pub fn pattern_directives(&self, pattern: Pattern) -> &[Directive] {
+        let range = self.patterns[pattern.idx()].directives.clone();
+        &self.directives[range.start as usize..range.end as usize]
+    }
+
+    /// Applies the `#offset!` and `#trim!` directives of `pattern` which target
+    /// `capture` to the captured `range`.
+    ///
+    /// The result is always contained in `range`: directives can shrink the captured range
+    /// but never extend it beyond the captured node.
+    ///
+    /// `input` is only read if the pattern has a `#trim!` directive or an `#offset!`
+    /// directive with row offsets for `capture`.
+    pub fn capture_range(
+        &self,
+        pattern: Pattern,
+        capture: Capture,
+        range: Range<u32>,
+        input: &mut dyn PredicateInput,
+    ) -> Range<u32> {
+        let adjusted = self
+            .pattern_directives(pattern)
+            .iter()
+            .filter(|directive| directive.capture() == capture)
+            .fold(range.clone(), |range, directive| {
+                directive.apply_to_range(range, input)
+            });
+        directive::clamp_range(adjusted, range)
+    }
+
+    /// Applies the `#gsub!` and `#strip!` directives of `pattern` which target
+    /// `capture` to the captured `text`.
+    pub fn capture_text<'a>(
+        &self,
+        pattern: Pattern,
+        capture: Capture,
+        text: Cow<'a, str>,
+    ) -> Cow<'a, str> {
+        self.pattern_directives(pattern)
+            .iter()
+            .filter(|directive| directive.capture() == capture)
+            .fold(text, |text, directive| directive.apply_to_text(text))
+    }
+
     /// Get the byte offset where the given pattern starts in the query's
     /// source.
     #[doc(alias = "ts_query_start_byte_for_pattern")]
     #[must_use]
     pub fn start_byte_for_pattern(&self, pattern: Pattern) -> usize {
+        self.check_pattern(pattern);
+        unsafe { ts_query_start_byte_for_pattern(self.raw, pattern.0) as usize }
+    }
+
+    /// Get the byte offset where the given pattern ends in the query's
+    /// source.
+    #[doc(alias = "ts_query_end_byte_for_pattern")]
+    #[must_use]
+    pub fn end_byte_for_pattern(&self, pattern: Pattern) -> usize {
+        self.check_pattern(pattern);
+        unsafe { ts_query_end_byte_for_pattern(self.raw, pattern.0) as usize }
+    }
+
+    /// Get the quantifier of `capture` within the given pattern.
+    ///
+    /// Captures that do not appear in the pattern have the quantifier
+    /// [`CaptureQuantifier::Zero`].
+    #[doc(alias = "ts_query_capture_quantifier_for_id")]
+    #[must_use]
+    pub fn capture_quantifier(&self, pattern: Pattern, capture: Capture) -> CaptureQuantifier {
+        self.check_pattern(pattern);
+        // need an assertions because the ts c api does not do bounds check
+        assert!(capture.0 < self.num_captures, "invalid capture index");
+        unsafe { ts_query_capture_quantifier_for_id(self.raw, pattern.0, capture.0) }
+    }
+
+    /// Get the quantifiers of all captures within the given pattern, indexed
+    /// by capture.
+    #[inline]
+    pub fn capture_quantifiers(
+        &self,
+        pattern: Pattern,
+    ) -> impl ExactSizeIterator<Item = (Capture, CaptureQuantifier)> + '_ {
+        self.check_pattern(pattern);
+        (0..self.num_captures).map(move |cap| {
+            let quantifier =
+                unsafe { ts_query_capture_quantifier_for_id(self.raw, pattern.0, cap) };
+            (Capture(cap), quantifier)
+        })
+    }
+
+    /// Check if the given pattern has a single root node.
+    #[doc(alias = "ts_query_is_pattern_rooted")]
+    #[must_use]
+    pub fn is_pattern_rooted(&self, pattern: Pattern) -> bool {
+        self.check_pattern(pattern);
+        unsafe { ts_query_is_pattern_rooted(self.raw, pattern.0) }
+    }
+
+    /// Check if the given pattern is "non-local".
+    ///
+    /// A non-local pattern has multiple root nodes and can match within a
+    /// repeating sequence of nodes, as specified by the grammar. Non-local
+    /// patterns disable certain optimizations that would otherwise be possible
+    /// when executing a query on a specific range of a syntax tree.
+    #[doc(alias = "ts_query_is_pattern_non_local")]
+    #[must_use]
+    pub fn is_pattern_non_local(&self, pattern: Pattern) -> bool {
+        self.check_pattern(pattern);
+        unsafe { ts_query_is_pattern_non_local(self.raw, pattern.0) }
+    }
+
+    /// Check if a pattern is guaranteed to match once the step at the given
+    /// byte offset in the query's source is reached.
+    #[doc(alias = "ts_query_is_pattern_guaranteed_at_step")]
+    #[must_use]
+    pub fn is_pattern_guaranteed_at_step(&self, byte_offset: usize) -> bool {
+        unsafe { ts_query_is_pattern_guaranteed_at_step(self.raw, byte_offset as u32) }
+    }
+
+    #[inline]
+    fn check_pattern(&self, pattern: Pattern) {
+        // need an assertions because the ts c api does not do bounds check
         assert!(
-            pattern.0 < self.text_predicates.len() as u32,
+            pattern.idx() < self.patterns.len(),
             "Pattern index is {pattern:?} but the pattern count is {}",
-            self.text_predicates.len(),
+            self.patterns.len(),
         );
-        unsafe { ts_query_start_byte_for_pattern(self.raw, pattern.0) as usize }
     }
 
     /// Get the number of patterns in the query.
@@ -307,6 +463,17 @@ impl Query {
             ts_query_disable_capture(self.raw, bytes.as_ptr(), bytes.len() as u32);
         }
     }
+
+    /// Disable a certain pattern within a query.
+    ///
+    /// This prevents the pattern from matching and removes most of the
+    /// overhead associated with the pattern. Currently, there is no way to
+    /// undo this.
+    #[doc(alias = "ts_query_disable_pattern")]
+    pub fn disable_pattern(&mut self, pattern: Pattern) {
+        self.check_pattern(pattern);
+        unsafe { ts_query_disable_pattern(self.raw, pattern.0) }
+    }
 }
 
 impl Drop for Query {
@@ -328,6 +495,30 @@ impl Capture {
     }
 }
 
+/// How often a capture may occur within a single match of a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum CaptureQuantifier {
+    /// The capture does not occur in the pattern.
+    Zero = 0,
+    ZeroOrOne,
+    ZeroOrMore,
+    One,
+    OneOrMore,
+}
+
+impl CaptureQuantifier {
+    /// Whether a single match may contain more than one node for the capture.
+    pub fn is_repeated(self) -> bool {
+        matches!(self, Self::ZeroOrMore | Self::OneOrMore)
+    }
+
+    /// Whether a match may not contain any node for the capture.
+    pub fn is_optional(self) -> bool {
+        matches!(self, Self::Zero | Self::ZeroOrOne | Self::ZeroOrMore)
+    }
+}
+
 /// A reference to a string stored in a query
 #[derive(Clone, Copy, Debug)]
 pub struct QueryStr(u32);
@@ -375,7 +566,10 @@ impl ParserErrorLocation {
                     .next_back()
                     .filter(|s| !s.is_empty())
                     .map(ToOwned::to_owned);
-                line_after = source[line_end + 1..]
+                // The last line is not followed by a newline.
+                line_after = source
+                    .get(line_end + 1..)
+                    .unwrap_or_default()
                     .lines()
                     .next()
                     .filter(|s| !s.is_empty())
@@ -503,9 +697,27 @@ extern "C" {
     /// source code strings.
     fn ts_query_start_byte_for_pattern(query: NonNull<QueryData>, pattern_index: u32) -> u32;
 
-    // fn ts_query_is_pattern_rooted(query: NonNull<QueryData>, pattern_index: u32) -> bool;
-    // fn ts_query_is_pattern_non_local(query: NonNull<QueryData>, pattern_index: u32) -> bool;
-    // fn ts_query_is_pattern_guaranteed_at_step(query: NonNull<QueryData>, byte_offset: u32) -> bool;
+    /// Get the byte offset where the given pattern ends in the query's
+    /// source. This can be useful when combining queries by concatenating their
+    /// source code strings.
+    fn ts_query_end_byte_for_pattern(query: NonNull<QueryData>, pattern_index: u32) -> u32;
+
+    /// Check if the given pattern in the query has a single root node.
+    fn ts_query_is_pattern_rooted(query: NonNull<QueryData>, pattern_index: u32) -> bool;
+
+    /// Check if the given pattern in the query is 'non local'.
+    ///
+    /// A non-local pattern has multiple root nodes and can match within a
+    /// repeating sequence of nodes, as specified by the grammar. Non-local
+    /// patterns disable certain optimizations that would otherwise be possible
+    /// when executing a query on a specific range of a syntax tree.
+    fn ts_query_is_pattern_non_local(query: NonNull<QueryData>, pattern_index: u32) -> bool;
+
+    /// Check if a given pattern is guaranteed to match once a given step is
+    /// reached. The step is specified by its byte offset in the query's source
+    /// code.
+    fn ts_query_is_pattern_guaranteed_at_step(query: NonNull<QueryData>, byte_offset: u32) -> bool;
+
     /// Get the name and length of one of the query's captures, or one of the
     /// query's string literals. Each capture and string is associated with a
     /// numeric id based on the order that it appeared in the query's source.
@@ -515,6 +727,14 @@ extern "C" {
         length: &mut u32,
     ) -> *const u8;
 
+    /// Get the quantifier of the query's captures. Each capture is associated
+    /// with a numeric id based on the order that it appeared in the query's source.
+    fn ts_query_capture_quantifier_for_id(
+        query: NonNull<QueryData>,
+        pattern_index: u32,
+        capture_index: u32,
+    ) -> CaptureQuantifier;
+
     fn ts_query_string_value_for_id(
         self_: NonNull<QueryData>,
         index: u32,
@@ -527,4 +747,10 @@ extern "C" {
     /// any resource usage associated with recording the capture. Currently, there
     /// is no way to undo this.
     fn ts_query_disable_capture(self_: NonNull<QueryData>, name: *const u8, length: u32);
+
+    /// Disable a certain pattern within a query.
+    ///
+    /// This prevents the pattern from matching and removes most of the overhead
+    /// associated with the pattern. Currently, there is no way to undo this.
+    fn ts_query_disable_pattern(self_: NonNull<QueryData>, pattern_index: u32);
 }
@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use regex_cursor::engines::meta::Regex;
+
+use crate::query::predicate::{InvalidPredicateError, Predicate, PredicateInput};
+use crate::query::Capture;
+
+/// A directive like `(#offset! @capture 0 1 0 -1)` which changes the range or the text
+/// of a capture.
+///
+/// Directives are applied in the order they appear in the pattern with
+/// [`Query::capture_range`](crate::Query::capture_range) and
+/// [`Query::capture_text`](crate::Query::capture_text).
+#[derive(Debug)]
+pub enum Directive {
+    /// `(#offset! @capture start_row start_col end_row end_col)` moves the start and end
+    /// of the captured range by the given number of rows and columns. Columns count code
+    /// units of the input's encoding (bytes for UTF-8) and are kept when moving to another
+    /// row.
+    Offset {
+        capture: Capture,
+        start_row: i32,
+        start_col: i32,
+        end_row: i32,
+        end_col: i32,
+    },
+    /// `(#trim! @capture [start_linewise start_charwise end_linewise end_charwise])` trims
+    /// blank lines (linewise) or whitespace (charwise) from the start and end of the
+    /// captured range. Each flag is either `0` or `1`. Without flags only blank lines at
+    /// the end are trimmed.
+    Trim {
+        capture: Capture,
+        start_linewise: bool,
+        start_charwise: bool,
+        end_linewise: bool,
+        end_charwise: bool,
+    },
+    /// `(#gsub! @capture "regex" "replacement")` replaces all matches of the regex in the
+    /// captured text. The replacement can refer to capture groups with `$1`.
+    Gsub {
+        capture: Capture,
+        regex: Regex,
+        replacement: Box<str>,
+    },
+    /// `(#strip! @capture "regex")` removes all matches of the regex from the captured
+    /// text.
+    Strip { capture: Capture, regex: Regex },
+}
+
+impl Directive {
+    pub fn parse(predicate: &Predicate) -> Result<Self, InvalidPredicateError> {
+        let capture = predicate.capture_arg(0)?;
+        let regex_arg = |i| {
+            let regex = predicate.str_arg(i)?;
+            Regex::new(regex).map_err(|err| {
+                InvalidPredicateError::from(format!("invalid regex '{regex}', {err}"))
+            })
+        };
+        let directive = match predicate.name() {
+            "offset!" => {
+                predicate.check_arg_count(5)?;
+                let mut offsets = [0; 4];
+                for (i, offset) in offsets.iter_mut().enumerate() {
+                    let arg = predicate.str_arg(i + 1)?;
+                    *offset = arg.parse().map_err(|_| {
+                        format!(
+                            "{}. argument to #offset! must be an integer, got {arg:?}",
+                            i + 1
+                        )
+                    })?;
+                }
+                let [start_row, start_col, end_row, end_col] = offsets;
+                Directive::Offset {
+                    capture,
+                    start_row,
+                    start_col,
+                    end_row,
+                    end_col,
+                }
+            }
+            "trim!" => {
+                let mut flags = [false, false, true, false];
+                if predicate.num_args() != 1 {
+                    predicate.check_arg_count(5)?;
+                    for (i, flag) in flags.iter_mut().enumerate() {
+                        *flag = match predicate.str_arg(i + 1)? {
+                            "0" => false,
+                            "1" => true,
+                            arg => {
+                                return Err(format!(
+                                    "{}. argument to #trim! must be 0 or 1, got {arg:?}",
+                                    i + 1
+                                )
+                                .into())
+                            }
+                        };
+                    }
+                }
+                let [start_linewise, start_charwise, end_linewise, end_charwise] = flags;
+                Directive::Trim {
+                    capture,
+                    start_linewise,
+                    start_charwise,
+                    end_linewise,
+                    end_charwise,
+                }
+            }
+            "gsub!" => {
+                predicate.check_arg_count(3)?;
+                Directive::Gsub {
+                    capture,
+                    regex: regex_arg(1)?,
+                    replacement: predicate.str_arg(2)?.into(),
+                }
+            }
+            "strip!" => {
+                predicate.check_arg_count(2)?;
+                Directive::Strip {
+                    capture,
+                    regex: regex_arg(1)?,
+                }
+            }
+            name => return Err(format!("unknown directive #{name}").into()),
+        };
+        Ok(directive)
+    }
+
+    pub fn capture(&self) -> Capture {
+        match *self {
+            Directive::Offset { capture, .. }
+            | Directive::Trim { capture, .. }
+            | Directive::Gsub { capture, .. }
+            | Directive::Strip { capture, .. } => capture,
+        }
+    }
+
+    /// Applies `#offset!` and `#trim!` to `range`. Other directives leave the range unchanged.
+    pub(super) fn apply_to_range(
+        &self,
+        range: Range<u32>,
+        input: &mut dyn PredicateInput,
+    ) -> Range<u32> {
+        match *self {
+            Directive::Offset {
+                start_row,
+                start_col,
+                end_row,
+                end_col,
+                ..
+            } => {
+                let start = offset(input, range.start, start_row, start_col);
+                start..offset(input, range.end, end_row, end_col).max(start)
+            }
+            Directive::Trim {
+                start_linewise,
+                start_charwise,
+                end_linewise,
+                end_charwise,
+                ..
+            } => {
+                let trimmed = trim(input, range.clone());
+                let start = if start_charwise {
+                    trimmed.first
+                } else if start_linewise {
+                    trimmed.line_start
+                } else {
+                    range.start
+                };
+                let end = if end_charwise {
+                    trimmed.last_end
+                } else if end_linewise {
+                    trimmed.line_end
+                } else {
+                    range.end
+                };
+                start..end
+            }
+            Directive::Gsub { .. } | Directive::Strip { .. } => range,
+        }
+    }
+
+    /// Applies `#gsub!` and `#strip!` to `text`. Other directives leave the text unchanged.
+    pub(super) fn apply_to_text<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+        let (regex, replacement) = match self {
+            Directive::Gsub {
+                regex, replacement, ..
+            } => (regex, &**replacement),
+            Directive::Strip { regex, .. } => (regex, ""),
+            Directive::Offset { .. } | Directive::Trim { .. } => return text,
+        };
+        let mut replaced = String::new();
+        let mut last_end = None;
+        for captures in regex.captures_iter(regex_cursor::Input::new(&*text)) {
+            let Some(m) = captures.get_match() else {
+                continue;
+            };
+            replaced.push_str(&text[last_end.unwrap_or(0)..m.start()]);
+            captures.interpolate_string_into(&text, replacement, &mut replaced);
+            last_end = Some(m.end());
+        }
+        match last_end {
+            Some(last_end) => {
+                replaced.push_str(&text[last_end..]);
+                Cow::Owned(replaced)
+            }
+            // nothing was replaced
+            None => text,
+        }
+    }
+}
+
+/// Clamps a range adjusted by directives to the `bounds` of the captured node.
+///
+/// Directives can shrink a captured range but never extend it: query iterators like the
+/// highlighter yield captures in the order of their start and rely on directives only moving
+/// the start of a capture forward.
+pub(super) fn clamp_range(range: Range<u32>, bounds: Range<u32>) -> Range<u32> {
+    let start = range.start.clamp(bounds.start, bounds.end);
+    start..range.end.clamp(start, bounds.end)
+}
+
+/// Moves `pos` by `rows` lines, keeping its column, and then by `cols` code units.
+fn offset(input: &mut dyn PredicateInput, pos: u32, rows: i32, cols: i32) -> u32 {
+    let pos = if rows == 0 {
+        pos
+    } else {
+        let col = pos - input.line_start(pos, 0);
+        input.line_start(pos, rows) + col
+    };
+    let cols = cols as i64 * input.unit_len() as i64;
+    (pos as i64 + cols).clamp(0, u32::MAX as i64) as u32
+}
+
+/// The offsets to which the start and end of a range are trimmed, in the units of the input.
+struct Trimmed {
+    /// The start of the first non-whitespace char.
+    first: u32,
+    /// The start of the line of the first non-whitespace char.
+    line_start: u32,
+    /// The end of the last non-whitespace char.
+    last_end: u32,
+    /// The end of the line of the last non-whitespace char, excluding the line break.
+    line_end: u32,
+}
+
+/// Finds the offsets to trim `range` to. Text that is entirely made of whitespace is not
+/// trimmed.
+fn trim(input: &mut dyn PredicateInput, range: Range<u32>) -> Trimmed {
+    let mut first = None;
+    let mut line_start = range.start;
+    let mut last_end = range.start;
+    let mut line_end = None;
+    let mut prev: Option<(u32, char)> = None;
+    input.for_each_char(range.clone(), &mut |offset, c| {
+        if let Some((prev_offset, prev_char)) = prev {
+            if !prev_char.is_whitespace() {
+                last_end = offset;
+            } else if prev_char == '\n' && first.is_none() {
+                line_start = offset;
+            }
+            if c == '\n' && first.is_some() && line_end.is_none() {
+                line_end = Some(if prev_char == '\r' {
+                    prev_offset
+                } else {
+                    offset
+                });
+            }
+        }
+        if !c.is_whitespace() {
+            first.get_or_insert(offset);
+            line_end = None;
+        }
+        prev = Some((offset, c));
+    });
+    let Some(first) = first else {
+        return Trimmed {
+            first: range.start,
+            line_start: range.start,
+            last_end: range.end,
+            line_end: range.end,
+        };
+    };
+    let line_end = match prev {
+        Some((_, c)) if !c.is_whitespace() => {
+            last_end = range.end;
+            range.end
+        }
+        Some((offset, '\r')) if line_end.is_none() => offset,
+        _ => line_end.unwrap_or(range.end),
+    };
+    Trimmed {
+        first,
+        line_start,
+        last_end,
+        line_end,
+    }
+}
@@ -1,13 +1,18 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::error::Error;
 use std::iter::zip;
 use std::ops::Range;
 use std::ptr::NonNull;
+use std::sync::Arc;
 use std::{fmt, slice};
 
+use crate::query::directive::Directive;
 use crate::query::property::QueryProperty;
 use crate::query::{Capture, Pattern, PatternData, Query, QueryData, QueryStr, UserPredicate};
-use crate::query_cursor::MatchedNode;
-use crate::Input;
+use crate::query_cursor::{MatchedNode, QueryMatch};
+use crate::utf16::{decode_utf16, input_bytes, utf16_chars};
+use crate::{Input, InputEncoding, Node};
 
 use regex_cursor::engines::meta::Regex;
 use regex_cursor::Cursor;
@@ -32,6 +37,14 @@ pub(super) enum TextPredicateKind {
     EqCapture(Capture),
     MatchString(Regex),
     AnyString(Box<[QueryStr]>),
+    /// The captured text contains any of the given strings.
+    Contains(Box<[QueryStr]>),
+    /// The kind of the captured node is one of the given kinds.
+    KindEq(Box<[QueryStr]>),
+    /// The kind of the captured node's parent is one of the given kinds.
+    HasParent(Box<[QueryStr]>),
+    /// The kind of any of the captured node's ancestors is one of the given kinds.
+    HasAncestor(Box<[QueryStr]>),
 }
 
 #[derive(Debug)]
@@ -57,10 +70,10 @@ fn input_matches_str<I: Input>(str: &str, range: Range<u32>, input: &mut I) -> b
     if cursor.chunk()[start_in_chunk..] != str[..cursor.chunk().len() - start_in_chunk] {
         return false;
     }
-    str = &str[..cursor.chunk().len() - start_in_chunk];
+    str = &str[cursor.chunk().len() - start_in_chunk..];
     while cursor.advance() {
         if str.len() <= cursor.chunk().len() {
-            return &cursor.chunk()[..range.end - cursor.offset()] == str;
+            return &cursor.chunk()[..str.len()] == str;
         }
         if &str[..cursor.chunk().len()] != cursor.chunk() {
             return false;
@@ -81,6 +94,33 @@ impl TextPredicate {
         }
     }
 
+    /// Evaluates the predicates which only look at the syntax tree and not at the text of
+    /// the captured node.
+    fn node_satisfied(&self, node: &Node, query: &Query) -> bool {
+        let kind_matches = |kinds: &[QueryStr], node: &Node| {
+            kinds
+                .iter()
+                .any(|&kind| query.get_string(kind) == node.kind())
+        };
+        match self.kind {
+            TextPredicateKind::KindEq(ref kinds) => kind_matches(kinds, node),
+            TextPredicateKind::HasParent(ref kinds) => node
+                .parent()
+                .is_some_and(|parent| kind_matches(kinds, &parent)),
+            TextPredicateKind::HasAncestor(ref kinds) => {
+                let mut ancestor = node.parent();
+                while let Some(node) = ancestor {
+                    if kind_matches(kinds, &node) {
+                        return true;
+                    }
+                    ancestor = node.parent();
+                }
+                false
+            }
+            _ => unreachable!("not a node predicate"),
+        }
+    }
+
     pub fn satisfied<I: Input>(
         &self,
         input: &mut I,
@@ -92,9 +132,9 @@ impl TextPredicate {
             .filter(|matched_node| matched_node.capture == self.capture);
         match self.kind {
             TextPredicateKind::EqString(str) => self.satisfied_helper(capture_nodes.map(|node| {
-                let range = node.node.byte_range();
-                input_matches_str(query.get_string(str), range.clone(), input)
+                CaptureText::new(input, node.node.byte_range()).eq(query.get_string(str))
             })),
+            // Both captures use the same encoding so the raw bytes can be compared.
             TextPredicateKind::EqCapture(other_capture) => {
                 let mut other_nodes = matched_nodes
                     .iter()
@@ -110,49 +150,341 @@ impl TextPredicate {
                 let consumed_all = capture_nodes.next().is_none() && other_nodes.next().is_none();
                 res && (!self.match_all || consumed_all)
             }
-            TextPredicateKind::MatchString(ref regex) => {
+            TextPredicateKind::MatchString(ref regex) => self.satisfied_helper(
+                capture_nodes
+                    .map(|node| CaptureText::new(input, node.node.byte_range()).is_match(regex)),
+            ),
+            TextPredicateKind::AnyString(ref strings) => {
                 self.satisfied_helper(capture_nodes.map(|node| {
-                    let range = node.node.byte_range();
-                    let mut input = regex_cursor::Input::new(input.cursor_at(range.start));
-                    input.slice(range.start as usize..range.end as usize);
-                    regex.is_match(input)
+                    let mut text = CaptureText::new(input, node.node.byte_range());
+                    strings.iter().any(|&str| text.eq(query.get_string(str)))
                 }))
             }
-            TextPredicateKind::AnyString(ref strings) => {
-                let strings = strings.iter().map(|&str| query.get_string(str));
+            TextPredicateKind::Contains(ref strings) => {
                 self.satisfied_helper(capture_nodes.map(|node| {
-                    let range = node.node.byte_range();
+                    let mut text = CaptureText::new(input, node.node.byte_range());
+                    let text = text.text();
                     strings
-                        .clone()
-                        .filter(|str| str.len() == range.len())
-                        .any(|str| input_matches_str(str, range.clone(), input))
+                        .iter()
+                        .any(|&str| text.contains(query.get_string(str)))
                 }))
             }
+            TextPredicateKind::KindEq(_)
+            | TextPredicateKind::HasParent(_)
+            | TextPredicateKind::HasAncestor(_) => self
+                .satisfied_helper(capture_nodes.map(|node| self.node_satisfied(&node.node, query))),
+        }
+    }
+}
+
+/// The text of a captured node as seen by text predicates. UTF-8 input is read in place
+/// while UTF-16 input is transcoded to UTF-8 once, before comparing it with strings from the
+/// query or running regexes.
+enum CaptureText<'a, I> {
+    Utf8 { input: &'a mut I, range: Range<u32> },
+    Utf16(String),
+}
+
+impl<'a, I: Input> CaptureText<'a, I> {
+    fn new(input: &'a mut I, range: Range<u32>) -> Self {
+        if input.encoding() == InputEncoding::Utf8 {
+            CaptureText::Utf8 { input, range }
+        } else {
+            CaptureText::Utf16(decode_utf16(input, range))
+        }
+    }
+
+    fn eq(&mut self, str: &str) -> bool {
+        match self {
+            CaptureText::Utf8 { input, range } => input_matches_str(str, range.clone(), *input),
+            CaptureText::Utf16(text) => text == str,
+        }
+    }
+
+    fn is_match(&mut self, regex: &Regex) -> bool {
+        match self {
+            CaptureText::Utf8 { input, range } => {
+                let mut input = regex_cursor::Input::new(input.cursor_at(range.start));
+                input.slice(range.start as usize..range.end as usize);
+                regex.is_match(input)
+            }
+            CaptureText::Utf16(text) => regex.is_match(regex_cursor::Input::new(text.as_str())),
+        }
+    }
+
+    fn text(&mut self) -> Cow<'_, str> {
+        match self {
+            CaptureText::Utf8 { input, range } => PredicateInput::text(*input, range.clone()),
+            CaptureText::Utf16(text) => Cow::Borrowed(text),
+        }
+    }
+}
+
+/// Read access to the text of the [`Input`] a query is executed on, for use by custom
+/// predicates registered in a [`PredicateRegistry`].
+pub trait PredicateInput {
+    /// Returns the text in the given byte range. UTF-16 input is transcoded to UTF-8 and
+    /// invalid UTF-8 is replaced with `U+FFFD`.
+    fn text(&mut self, range: Range<u32>) -> Cow<'_, str>;
+
+    /// Calls `f` with every char in the given byte range and the byte offset at which the char
+    /// starts. Unlike the positions within [`text`](Self::text) the offsets count bytes of the
+    /// input's own encoding. Invalid UTF-8 sequences and unpaired surrogates are passed as
+    /// `U+FFFD` at the offset where they start.
+    fn for_each_char(&mut self, range: Range<u32>, f: &mut dyn FnMut(u32, char));
+
+    /// The number of bytes of a code unit of the input's encoding: 1 for UTF-8 and 2 for
+    /// UTF-16.
+    fn unit_len(&self) -> u32;
+
+    /// Returns the byte offset at which the line `rows` lines after the line containing
+    /// `offset` starts, or before it if `rows` is negative. Lines before the start of the
+    /// input start at 0 and lines after its end start at the end of the input.
+    fn line_start(&mut self, offset: u32, rows: i32) -> u32;
+}
+
+impl<I: Input> PredicateInput for I {
+    fn text(&mut self, range: Range<u32>) -> Cow<'_, str> {
+        if self.encoding() != InputEncoding::Utf8 {
+            return decode_utf16(self, range).into();
+        }
+        let cursor = self.cursor_at(range.start);
+        let start_in_chunk = range.start as usize - cursor.offset();
+        let end_in_chunk = range.end as usize - cursor.offset();
+        if end_in_chunk <= cursor.chunk().len() {
+            // hotpath: the text is contained in a single chunk and can be borrowed
+            let cursor = &*self.cursor_at(range.start);
+            return String::from_utf8_lossy(&cursor.chunk()[start_in_chunk..end_in_chunk]);
+        }
+        let mut bytes = cursor.chunk()[start_in_chunk..].to_vec();
+        while bytes.len() < range.len() && cursor.advance() {
+            let len = (range.len() - bytes.len()).min(cursor.chunk().len());
+            bytes.extend_from_slice(&cursor.chunk()[..len]);
+        }
+        String::from_utf8_lossy(&bytes).into_owned().into()
+    }
+
+    fn for_each_char(&mut self, range: Range<u32>, f: &mut dyn FnMut(u32, char)) {
+        let encoding = self.encoding();
+        let bytes = input_bytes(self, range.clone());
+        let mut offset = range.start;
+        if encoding != InputEncoding::Utf8 {
+            for (c, len) in utf16_chars(&bytes, encoding == InputEncoding::Utf16BE) {
+                f(offset, c);
+                offset += len;
+            }
+            return;
+        }
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let (valid, invalid_len) = match std::str::from_utf8(rest) {
+                Ok(valid) => (valid, 0),
+                Err(err) => {
+                    let valid = std::str::from_utf8(&rest[..err.valid_up_to()]).unwrap();
+                    let invalid_len = err.error_len().unwrap_or(rest.len() - err.valid_up_to());
+                    (valid, invalid_len)
+                }
+            };
+            for (i, c) in valid.char_indices() {
+                f(offset + i as u32, c);
+            }
+            offset += valid.len() as u32;
+            if invalid_len != 0 {
+                f(offset, char::REPLACEMENT_CHARACTER);
+                offset += invalid_len as u32;
+            }
+            rest = &rest[valid.len() + invalid_len..];
+        }
+    }
+
+    fn unit_len(&self) -> u32 {
+        if self.encoding() == InputEncoding::Utf8 {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn line_start(&mut self, offset: u32, rows: i32) -> u32 {
+        let unit = self.unit_len() as usize;
+        let line_break: &[u8] = if unit == 1 {
+            b"\n"
+        } else if self.encoding() == InputEncoding::Utf16BE {
+            b"\0\n"
+        } else {
+            b"\n\0"
+        };
+        let offset = offset as usize;
+        let cursor = self.cursor_at(offset as u32);
+        // Chunks start and end at codepoint boundaries so code units are aligned within them.
+        if rows > 0 {
+            // The line starts after the `rows`-th line break at or after `offset`.
+            let mut remaining = rows;
+            loop {
+                let chunk_start = cursor.offset();
+                let chunk = cursor.chunk();
+                let start = offset.saturating_sub(chunk_start);
+                for pos in (start..chunk.len()).step_by(unit) {
+                    if chunk[pos..].starts_with(line_break) {
+                        remaining -= 1;
+                        if remaining == 0 {
+                            return (chunk_start + pos + unit) as u32;
+                        }
+                    }
+                }
+                let chunk_end = chunk_start + chunk.len();
+                if !cursor.advance() {
+                    return chunk_end as u32;
+                }
+            }
+        }
+        // The line starts after the `1 - rows`-th line break before `offset`.
+        let mut remaining = 1 - rows as i64;
+        loop {
+            let chunk_start = cursor.offset();
+            let chunk = cursor.chunk();
+            let end = (offset - chunk_start).min(chunk.len());
+            for pos in (0..end).step_by(unit).rev() {
+                if chunk[pos..].starts_with(line_break) {
+                    remaining -= 1;
+                    if remaining == 0 {
+                        return (chunk_start + pos + unit) as u32;
+                    }
+                }
+            }
+            if !cursor.backtrack() {
+                return 0;
+            }
         }
     }
 }
 
+type PredicateEvaluator =
+    dyn Fn(&Predicate<'_>, &QueryMatch<'_, '_>, &mut dyn PredicateInput) -> bool + Send + Sync;
+
+/// A set of named predicates which are evaluated whenever a query produces a match.
+///
+/// Queries created with [`Query::with_predicates`] resolve predicates with a registered
+/// name to the registered evaluator instead of passing them to the `custom_predicate`
+/// callback. Query cursors only yield matches for which all evaluators return `true`.
+#[derive(Default, Clone)]
+pub struct PredicateRegistry {
+    evaluators: HashMap<Box<str>, Arc<PredicateEvaluator>>,
+}
+
+impl PredicateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an evaluator for the predicate `#<name>`. The name includes the trailing
+    /// `?`, for example `"is-upper-case?"`. Registering a name twice replaces the previous
+    /// evaluator.
+    ///
+    /// The evaluator receives the predicate (to access its arguments), the match that
+    /// should be filtered and the text the query is executed on.
+    pub fn register(
+        &mut self,
+        name: impl Into<Box<str>>,
+        evaluator: impl Fn(&Predicate<'_>, &QueryMatch<'_, '_>, &mut dyn PredicateInput) -> bool
+            + Send
+            + Sync
+            + 'static,
+    ) -> &mut Self {
+        self.evaluators.insert(name.into(), Arc::new(evaluator));
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.evaluators.contains_key(name)
+    }
+}
+
+impl fmt::Debug for PredicateRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.evaluators.keys()).finish()
+    }
+}
+
+/// A predicate of a pattern which is evaluated by an evaluator from a [`PredicateRegistry`].
+pub(crate) struct CustomPredicate {
+    evaluator: Arc<PredicateEvaluator>,
+    pattern: Pattern,
+    /// The range of the predicate's steps in [`predicate_steps`].
+    steps: Range<u32>,
+}
+
+impl CustomPredicate {
+    pub fn satisfied(
+        &self,
+        query_match: &QueryMatch,
+        input: &mut dyn PredicateInput,
+        query: &Query,
+    ) -> bool {
+        let steps = unsafe { predicate_steps(query.raw, self.pattern) };
+        let steps = &steps[self.steps.start as usize..self.steps.end as usize];
+        let predicate = Predicate {
+            name: QueryStr(steps[0].value_id),
+            args: &steps[1..],
+            query,
+        };
+        (self.evaluator)(&predicate, query_match, input)
+    }
+}
+
+impl fmt::Debug for CustomPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomPredicate")
+            .field("pattern", &self.pattern)
+            .field("steps", &self.steps)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the predicate steps of `pattern`.
+///
+/// # Safety
+///
+/// The returned slice is owned by the query and must not outlive it.
+unsafe fn predicate_steps<'a>(query: NonNull<QueryData>, pattern: Pattern) -> &'a [PredicateStep] {
+    let mut len = 0u32;
+    let raw_predicates = ts_query_predicates_for_pattern(query, pattern.0, &mut len);
+    if len != 0 {
+        slice::from_raw_parts(raw_predicates, len as usize)
+    } else {
+        &[]
+    }
+}
+
 impl Query {
     pub(super) fn parse_pattern_predicates(
         &mut self,
         pattern: Pattern,
+        registry: &PredicateRegistry,
         mut custom_predicate: impl FnMut(Pattern, UserPredicate) -> Result<(), InvalidPredicateError>,
     ) -> Result<PatternData, InvalidPredicateError> {
         let text_predicate_start = self.text_predicates.len() as u32;
+        let custom_predicate_start = self.custom_predicates.len() as u32;
+        let directive_start = self.directives.len() as u32;
 
-        let predicate_steps = unsafe {
-            let mut len = 0u32;
-            let raw_predicates = ts_query_predicates_for_pattern(self.raw, pattern.0, &mut len);
-            (len != 0)
-                .then(|| slice::from_raw_parts(raw_predicates, len as usize))
-                .unwrap_or_default()
-        };
+        let predicate_steps = unsafe { predicate_steps(self.raw, pattern) };
         let predicates = predicate_steps
             .split(|step| step.kind == PredicateStepKind::Done)
             .filter(|predicate| !predicate.is_empty());
 
-        for predicate in predicates {
-            let predicate = unsafe { Predicate::new(self, predicate)? };
+        for steps in predicates {
+            let predicate = unsafe { Predicate::new(self, steps)? };
+
+            if let Some(evaluator) = registry.evaluators.get(predicate.name()) {
+                // SAFETY: `steps` is a subslice of `predicate_steps`
+                let start = unsafe { steps.as_ptr().offset_from(predicate_steps.as_ptr()) } as u32;
+                self.custom_predicates.push(CustomPredicate {
+                    evaluator: evaluator.clone(),
+                    pattern,
+                    steps: start..start + steps.len() as u32,
+                });
+                continue;
+            }
 
             match predicate.name() {
                 "eq?" | "not-eq?" | "any-eq?" | "any-not-eq?" => {
@@ -193,6 +525,50 @@ impl Query {
                     });
                 }
 
+                "contains?" | "not-contains?" => {
+                    predicate.check_min_arg_count(2)?;
+                    let capture_idx = predicate.capture_arg(0)?;
+                    let strings: Result<_, InvalidPredicateError> = (1..predicate.num_args())
+                        .map(|i| predicate.query_str_arg(i))
+                        .collect();
+                    self.text_predicates.push(TextPredicate {
+                        capture: capture_idx,
+                        kind: TextPredicateKind::Contains(strings?),
+                        negated: predicate.name() == "not-contains?",
+                        match_all: true,
+                    });
+                }
+
+                "kind-eq?" | "not-kind-eq?" | "has-parent?" | "not-has-parent?"
+                | "has-ancestor?" | "not-has-ancestor?" => {
+                    predicate.check_min_arg_count(2)?;
+                    let capture = predicate.capture_arg(0)?;
+                    let kinds: Result<_, InvalidPredicateError> = (1..predicate.num_args())
+                        .map(|i| predicate.query_str_arg(i))
+                        .collect();
+                    let kinds = kinds?;
+                    let (negated, kind) = match predicate.name() {
+                        "kind-eq?" => (false, TextPredicateKind::KindEq(kinds)),
+                        "not-kind-eq?" => (true, TextPredicateKind::KindEq(kinds)),
+                        "has-parent?" => (false, TextPredicateKind::HasParent(kinds)),
+                        "not-has-parent?" => (true, TextPredicateKind::HasParent(kinds)),
+                        "has-ancestor?" => (false, TextPredicateKind::HasAncestor(kinds)),
+                        _ => (true, TextPredicateKind::HasAncestor(kinds)),
+                    };
+                    self.text_predicates.push(TextPredicate {
+                        capture,
+                        kind,
+                        negated,
+                        match_all: true,
+                    });
+                }
+
+                "offset!" | "trim!" | "gsub!" | "strip!" => {
+                    predicate.check_min_arg_count(1)?;
+                    let directive = Directive::parse(&predicate)?;
+                    self.directives.push(directive);
+                }
+
                 "set!" => {
                     let property = QueryProperty::parse(&predicate)?;
                     custom_predicate(
@@ -238,6 +614,8 @@ impl Query {
         }
         Ok(PatternData {
             text_predicates: text_predicate_start..self.text_predicates.len() as u32,
+            custom_predicates: custom_predicate_start..self.custom_predicates.len() as u32,
+            directives: directive_start..self.directives.len() as u32,
         })
     }
 }
@@ -1,13 +1,17 @@
 use core::slice;
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::Range;
+use std::os::raw::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::ptr::{self, NonNull};
+use std::time::{Duration, Instant};
 
 use crate::node::NodeRaw;
 use crate::query::{Capture, Pattern, Query, QueryData};
-use crate::{Input, IntoInput, Node, Tree};
+use crate::{Input, IntoInput, Node, Point, ProgressCallback, Tree};
 
 enum QueryCursorData {}
 
@@ -25,9 +29,55 @@ pub struct QueryCursor<'a, 'tree, I: Input> {
     ptr: NonNull<QueryCursorData>,
     tree: PhantomData<&'tree Tree>,
     input: I,
+    containing_range: Option<Range<u32>>,
+    /// Owned (created with `Box::into_raw`), the C library holds a pointer to the
+    /// contained options while the query executes.
+    progress: Option<NonNull<QueryProgress>>,
 }
 
-impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
+impl<'a, 'tree, I: Input> QueryCursor<'a, 'tree, I> {
+    fn satisfies_predicates(
+        &mut self,
+        query_match: &TSQueryMatch,
+        matched_nodes: &[MatchedNode<'tree>],
+    ) -> bool {
+        if let Some(range) = &self.containing_range {
+            let contained = matched_nodes.iter().all(|matched_node| {
+                let node_range = matched_node.node.byte_range();
+                range.start <= node_range.start && node_range.end <= range.end
+            });
+            if !contained {
+                return false;
+            }
+        }
+        let satisfies_text_predicates = self
+            .query
+            .pattern_text_predicates(query_match.pattern_index)
+            .iter()
+            .all(|predicate| predicate.satisfied(&mut self.input, matched_nodes, self.query));
+        if !satisfies_text_predicates {
+            return false;
+        }
+        let custom_predicates = self
+            .query
+            .pattern_custom_predicates(query_match.pattern_index);
+        if custom_predicates.is_empty() {
+            return true;
+        }
+        let query_match = QueryMatch {
+            id: query_match.id,
+            pattern: Pattern(query_match.pattern_index as u32),
+            matched_nodes,
+            // SAFETY: the match is only borrowed by the predicates and dropped before the
+            // cursor is used again
+            query_cursor: unsafe { &mut *self.ptr.as_ptr() },
+            _tree: PhantomData,
+        };
+        custom_predicates
+            .iter()
+            .all(|predicate| predicate.satisfied(&query_match, &mut self.input, self.query))
+    }
+
     pub fn next_match(&mut self) -> Option<QueryMatch<'_, 'tree>> {
         let mut query_match = TSQueryMatch {
             id: 0,
@@ -36,6 +86,9 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
             captures: ptr::null(),
         };
         loop {
+            if self.was_cancelled() {
+                return None;
+            }
             let success =
                 unsafe { ts_query_cursor_next_match(self.ptr.as_ptr(), &mut query_match) };
             if !success {
@@ -47,12 +100,7 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
                     query_match.capture_count as usize,
                 )
             };
-            let satisfies_predicates = self
-                .query
-                .pattern_text_predicates(query_match.pattern_index)
-                .iter()
-                .all(|predicate| predicate.satisfied(&mut self.input, matched_nodes, self.query));
-            if satisfies_predicates {
+            if self.satisfies_predicates(&query_match, matched_nodes) {
                 let res = QueryMatch {
                     id: query_match.id,
                     pattern: Pattern(query_match.pattern_index as u32),
@@ -74,6 +122,9 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
         };
         let mut capture_idx = 0;
         loop {
+            if self.was_cancelled() {
+                return None;
+            }
             let success = unsafe {
                 ts_query_cursor_next_capture(self.ptr.as_ptr(), &mut query_match, &mut capture_idx)
             };
@@ -86,12 +137,7 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
                     query_match.capture_count as usize,
                 )
             };
-            let satisfies_predicates = self
-                .query
-                .pattern_text_predicates(query_match.pattern_index)
-                .iter()
-                .all(|predicate| predicate.satisfied(&mut self.input, matched_nodes, self.query));
-            if satisfies_predicates {
+            if self.satisfies_predicates(&query_match, matched_nodes) {
                 let res = QueryMatch {
                     id: query_match.id,
                     pattern: Pattern(query_match.pattern_index as u32),
@@ -108,14 +154,52 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
         }
     }
 
-    pub fn set_byte_range(&mut self, range: Range<u32>) {
-        unsafe {
-            ts_query_cursor_set_byte_range(self.ptr.as_ptr(), range.start, range.end);
-        }
+    /// Iterate over the individual captures of all matches in the order of their start
+    /// byte (see [`QueryCursor::next_matched_node`]). Matches which don't satisfy the
+    /// predicates of their pattern are skipped.
+    ///
+    /// Each item is the match and the index of the yielded capture within
+    /// [`QueryMatch::matched_nodes`]. A match with multiple captures is yielded once for
+    /// each of its captures.
+    pub fn captures(&mut self) -> Captures<'_, 'a, 'tree, I> {
+        Captures { cursor: self }
+    }
+
+    /// Set the range of bytes in which the query will be executed, see
+    /// [`InactiveQueryCursor::set_byte_range`].
+    #[doc(alias = "ts_query_cursor_set_byte_range")]
+    pub fn set_byte_range(&mut self, range: Range<u32>) -> Result<(), InvalidRangeError> {
+        set_byte_range(self.ptr, range)
+    }
+
+    /// Whether the timeout expired or the progress callback returned `true` during this
+    /// execution. A cancelled cursor doesn't return any further matches.
+    #[must_use]
+    pub fn was_cancelled(&self) -> bool {
+        self.progress
+            .is_some_and(|progress| unsafe { progress.as_ref().cancelled })
     }
 
-    pub fn reuse(self) -> InactiveQueryCursor {
-        let res = InactiveQueryCursor { ptr: self.ptr };
+    fn take_progress(&mut self) -> Option<Box<QueryProgress>> {
+        self.progress
+            .take()
+            .map(|progress| unsafe { Box::from_raw(progress.as_ptr()) })
+    }
+
+    /// Stop executing the query and return the cursor so that it can execute another
+    /// query. The settings of the cursor (ranges, limits, timeout and progress callback)
+    /// are kept.
+    pub fn reuse(mut self) -> InactiveQueryCursor {
+        let progress = self.take_progress();
+        let (timeout, progress_callback) = progress.map_or((None, None), |progress| {
+            (progress.timeout, progress.callback)
+        });
+        let res = InactiveQueryCursor {
+            ptr: self.ptr,
+            containing_range: self.containing_range.take(),
+            timeout,
+            progress_callback,
+        };
         mem::forget(self);
         res
     }
@@ -123,23 +207,97 @@ impl<'tree, I: Input> QueryCursor<'_, 'tree, I> {
 
 impl<I: Input> Drop for QueryCursor<'_, '_, I> {
     fn drop(&mut self) {
-        unsafe { with_cache(|cache| cache.push(InactiveQueryCursor { ptr: self.ptr })) }
+        drop(self.take_progress());
+        unsafe { with_cache(|cache| cache.push(InactiveQueryCursor::from_raw(self.ptr))) }
+    }
+}
+
+/// The captures of a [`QueryCursor`] in document order, created with
+/// [`QueryCursor::captures`].
+///
+/// [`QueryMatch`] borrows memory of the cursor which is reused once the cursor advances, so
+/// this can't implement [`Iterator`]. Use `while let Some((query_match, capture_idx)) =
+/// captures.next()` instead, and [`QueryMatch::remove`] to skip the remaining captures of
+/// a match.
+pub struct Captures<'cursor, 'a, 'tree, I: Input> {
+    cursor: &'cursor mut QueryCursor<'a, 'tree, I>,
+}
+
+impl<'tree, I: Input> Captures<'_, '_, 'tree, I> {
+    /// Returns the next capture and the match it belongs to, see
+    /// [`QueryCursor::next_matched_node`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(QueryMatch<'_, 'tree>, MatchedNodeIdx)> {
+        self.cursor.next_matched_node()
+    }
+}
+
+/// State shared with the C library to check the timeout and invoke the progress callback
+/// of a [`QueryCursor`].
+struct QueryProgress {
+    options: QueryCursorOptions,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    callback: Option<ProgressCallback>,
+    cancelled: bool,
+}
+
+impl QueryProgress {
+    unsafe extern "C" fn check(state: NonNull<QueryCursorState>) -> bool {
+        let state = state.as_ref();
+        let progress: &mut QueryProgress = state.payload.cast().as_mut();
+        let cancel = progress
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+            || match &progress.callback {
+                // Unwinding across the FFI boundary is UB so a panicking
+                // callback cancels the query instead.
+                Some(callback) => {
+                    catch_unwind(AssertUnwindSafe(|| callback(state.current_byte_offset)))
+                        .unwrap_or(true)
+                }
+                None => false,
+            };
+        // The C library only stops returning matches from the current call to
+        // `next_match`/`next_capture` so the cancellation is remembered here.
+        progress.cancelled |= cancel;
+        cancel
     }
 }
 
 /// A query cursor that is not actively associated with a query
 pub struct InactiveQueryCursor {
     ptr: NonNull<QueryCursorData>,
+    containing_range: Option<Range<u32>>,
+    timeout: Option<Duration>,
+    progress_callback: Option<ProgressCallback>,
 }
 
 impl InactiveQueryCursor {
     pub fn new() -> Self {
-        unsafe {
-            with_cache(|cache| {
-                cache.pop().unwrap_or_else(|| InactiveQueryCursor {
-                    ptr: NonNull::new_unchecked(ts_query_cursor_new()),
-                })
-            })
+        let cached = unsafe { with_cache(|cache| cache.pop()) };
+        match cached {
+            Some(mut cursor) => {
+                // Cached cursors may still have the ranges of a previous execution.
+                unsafe {
+                    ts_query_cursor_set_byte_range(cursor.ptr.as_ptr(), 0, u32::MAX);
+                    ts_query_cursor_set_point_range(cursor.ptr.as_ptr(), Point::ZERO, Point::MAX);
+                }
+                cursor.set_max_start_depth(None);
+                cursor
+            }
+            None => unsafe {
+                InactiveQueryCursor::from_raw(NonNull::new_unchecked(ts_query_cursor_new()))
+            },
+        }
+    }
+
+    fn from_raw(ptr: NonNull<QueryCursorData>) -> Self {
+        InactiveQueryCursor {
+            ptr,
+            containing_range: None,
+            timeout: None,
+            progress_callback: None,
         }
     }
 
@@ -167,26 +325,117 @@ impl InactiveQueryCursor {
         unsafe { ts_query_cursor_did_exceed_match_limit(self.ptr.as_ptr()) }
     }
 
-    pub fn set_byte_range(&mut self, range: Range<u32>) {
+    /// Set the range of bytes in which the query will be executed.
+    ///
+    /// All matches that intersect the range are returned, so captures of a match may lie
+    /// outside of it. Fails if the start of the range is after its end.
+    #[doc(alias = "ts_query_cursor_set_byte_range")]
+    pub fn set_byte_range(&mut self, range: Range<u32>) -> Result<(), InvalidRangeError> {
+        set_byte_range(self.ptr, range)
+    }
+
+    /// Set the range of (row, column) positions in which the query will be executed.
+    ///
+    /// Like [`InactiveQueryCursor::set_byte_range`] this returns all matches that
+    /// intersect the range, so captures of a match may lie outside of it. Fails if the
+    /// start of the range is after its end.
+    #[doc(alias = "ts_query_cursor_set_point_range")]
+    pub fn set_point_range(&mut self, range: Range<Point>) -> Result<(), InvalidRangeError> {
+        let success =
+            unsafe { ts_query_cursor_set_point_range(self.ptr.as_ptr(), range.start, range.end) };
+        if success {
+            Ok(())
+        } else {
+            Err(InvalidRangeError)
+        }
+    }
+
+    /// Only return matches where all captured nodes are fully contained in the given
+    /// byte range. Passing `None` removes the restriction.
+    ///
+    /// In contrast to [`InactiveQueryCursor::set_byte_range`] (which finds all matches
+    /// intersecting a range) this excludes matches which extend past the range. Both can
+    /// be combined, for example to find the matches intersecting the visible rows as
+    /// long as they are contained in a slightly larger range.
+    pub fn set_containing_byte_range(&mut self, range: Option<Range<u32>>) {
+        self.containing_range = range;
+    }
+
+    /// Set the maximum depth (relative to the node passed to
+    /// [`InactiveQueryCursor::execute_query`]) at which the root node of a pattern may
+    /// match. A depth of zero only matches patterns starting at the node itself. `None`
+    /// removes the limit.
+    ///
+    /// Note that only the start of a pattern is limited: the rest of the pattern may
+    /// still match nodes at any depth.
+    #[doc(alias = "ts_query_cursor_set_max_start_depth")]
+    pub fn set_max_start_depth(&mut self, max_start_depth: Option<u32>) {
         unsafe {
-            ts_query_cursor_set_byte_range(self.ptr.as_ptr(), range.start, range.end);
+            ts_query_cursor_set_max_start_depth(
+                self.ptr.as_ptr(),
+                max_start_depth.unwrap_or(u32::MAX),
+            );
         }
     }
 
+    /// Set the maximum duration a single execution of a query may take. The timeout
+    /// starts when [`InactiveQueryCursor::execute_query`] is called. Once it has expired
+    /// the cursor stops returning matches and [`QueryCursor::was_cancelled`] returns
+    /// `true`. `None` removes the timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Set a callback which is invoked periodically while the query executes with the
+    /// current byte offset of the cursor. The query is cancelled (see
+    /// [`QueryCursor::was_cancelled`]) as soon as the callback returns `true`.
+    pub fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.progress_callback = callback;
+    }
+
     pub fn execute_query<'a, 'tree, I: IntoInput>(
-        self,
+        mut self,
         query: &'a Query,
         node: &Node<'tree>,
         input: I,
     ) -> QueryCursor<'a, 'tree, I::Input> {
         let ptr = self.ptr;
-        unsafe { ts_query_cursor_exec(ptr.as_ptr(), query.raw.as_ref(), node.as_raw()) };
+        let containing_range = self.containing_range.take();
+        let progress = if self.timeout.is_some() || self.progress_callback.is_some() {
+            let progress = Box::new(QueryProgress {
+                options: QueryCursorOptions {
+                    payload: None,
+                    progress_callback: Some(QueryProgress::check),
+                },
+                timeout: self.timeout,
+                deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+                callback: self.progress_callback.take(),
+                cancelled: false,
+            });
+            let progress = NonNull::from(Box::leak(progress));
+            unsafe {
+                let options = ptr::addr_of_mut!((*progress.as_ptr()).options);
+                (*options).payload = Some(progress.cast());
+                ts_query_cursor_exec_with_options(
+                    ptr.as_ptr(),
+                    query.raw.as_ref(),
+                    node.as_raw(),
+                    options,
+                );
+            }
+            Some(progress)
+        } else {
+            unsafe { ts_query_cursor_exec(ptr.as_ptr(), query.raw.as_ref(), node.as_raw()) };
+            None
+        };
         mem::forget(self);
         QueryCursor {
             query,
             ptr,
             tree: PhantomData,
             input: input.into_input(),
+            containing_range,
+            progress,
         }
     }
 }
@@ -203,6 +452,29 @@ impl Drop for InactiveQueryCursor {
     }
 }
 
+fn set_byte_range(
+    ptr: NonNull<QueryCursorData>,
+    range: Range<u32>,
+) -> Result<(), InvalidRangeError> {
+    if unsafe { ts_query_cursor_set_byte_range(ptr.as_ptr(), range.start, range.end) } {
+        Ok(())
+    } else {
+        Err(InvalidRangeError)
+    }
+}
+
+/// An error returned when setting a query cursor range whose start is after its end.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidRangeError;
+
+impl fmt::Display for InvalidRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the start of the range is after its end")
+    }
+}
+
+impl std::error::Error for InvalidRangeError {}
+
 pub type MatchedNodeIdx = u32;
 
 #[repr(C)]
@@ -283,6 +555,27 @@ struct TSQueryMatch {
     captures: *const TSQueryCapture,
 }
 
+// `TSQueryCursorState`
+#[repr(C)]
+#[derive(Debug)]
+struct QueryCursorState {
+    /// The payload passed via `QueryCursorOptions`' `payload` field.
+    payload: NonNull<c_void>,
+    current_byte_offset: u32,
+}
+
+/// A function that accepts the current cursor state and returns `true` when the query
+/// should be cancelled.
+type RawQueryProgressCallback = unsafe extern "C" fn(state: NonNull<QueryCursorState>) -> bool;
+
+// `TSQueryCursorOptions`
+#[repr(C)]
+#[derive(Debug)]
+struct QueryCursorOptions {
+    payload: Option<NonNull<c_void>>,
+    progress_callback: Option<RawQueryProgressCallback>,
+}
+
 extern "C" {
     /// Advance to the next capture of the currently running query.
     /// If there is a capture, write its match to `*match` and its index within
@@ -325,6 +618,14 @@ extern "C" {
 
     /// Start running a given query on a given node.
     fn ts_query_cursor_exec(self_: *mut QueryCursorData, query: &QueryData, node: NodeRaw);
+    /// Start running a given query on a given node, with some options. The options
+    /// must stay alive while the query is executed.
+    fn ts_query_cursor_exec_with_options(
+        self_: *mut QueryCursorData,
+        query: &QueryData,
+        node: NodeRaw,
+        query_options: *const QueryCursorOptions,
+    );
     /// Manage the maximum number of in-progress matches allowed by this query
     /// cursor.
     ///
@@ -339,6 +640,18 @@ extern "C" {
     fn ts_query_cursor_set_match_limit(self_: *mut QueryCursorData, limit: u32);
     /// Set the range of bytes or (row, column) positions in which the query
     /// will be executed.
-    fn ts_query_cursor_set_byte_range(self_: *mut QueryCursorData, start_byte: u32, end_byte: u32);
+    fn ts_query_cursor_set_byte_range(
+        self_: *mut QueryCursorData,
+        start_byte: u32,
+        end_byte: u32,
+    ) -> bool;
+    fn ts_query_cursor_set_point_range(
+        self_: *mut QueryCursorData,
+        start_point: Point,
+        end_point: Point,
+    ) -> bool;
+    /// Set the maximum start depth for a query cursor. Set to `u32::MAX` to remove the
+    /// maximum start depth.
+    fn ts_query_cursor_set_max_start_depth(self_: *mut QueryCursorData, max_start_depth: u32);
 
 }
@@ -1,9 +1,9 @@
 use std::ops;
 
 use regex_cursor::{Cursor, RopeyCursor};
-use ropey::RopeSlice;
+use ropey::{Rope, RopeSlice};
 
-use crate::{Input, IntoInput};
+use crate::{ChunkedText, Input, IntoInput};
 
 pub struct RopeInput<'a> {
     src: RopeSlice<'a>,
@@ -64,3 +64,25 @@ impl<'a> Input for RopeInput<'a> {
         range1 == range2
     }
 }
+
+impl<'a> ChunkedText<'a> for RopeSlice<'a> {
+    fn len_bytes(self) -> usize {
+        RopeSlice::len_bytes(&self)
+    }
+
+    fn chunk_at_byte(self, byte_idx: usize) -> (&'a [u8], usize) {
+        let (chunk, start, _, _) = RopeSlice::chunk_at_byte(&self, byte_idx);
+        (chunk.as_bytes(), start)
+    }
+}
+
+impl<'a> ChunkedText<'a> for &'a Rope {
+    fn len_bytes(self) -> usize {
+        Rope::len_bytes(self)
+    }
+
+    fn chunk_at_byte(self, byte_idx: usize) -> (&'a [u8], usize) {
+        let (chunk, start, _, _) = Rope::chunk_at_byte(self, byte_idx);
+        (chunk.as_bytes(), start)
+    }
+}
@@ -1,8 +1,16 @@
-use std::fmt;
+use std::ffi::c_void;
+#[cfg(unix)]
+use std::os::fd::{AsFd, AsRawFd};
 use std::ptr::NonNull;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::{env, fs, io, process};
+use std::{fmt, slice};
 
 use crate::node::{Node, NodeRaw};
-use crate::{Point, TreeCursor};
+use crate::LookaheadIterator;
+use crate::{Point, Range, TreeCursor};
 
 // opaque pointers
 pub(super) enum SyntaxTreeData {}
@@ -20,6 +28,13 @@ impl Tree {
         self.ptr
     }
 
+    /// Creates a shallow copy of the tree behind `raw`, see [`Tree::clone`].
+    pub(super) unsafe fn copy_from_raw(raw: NonNull<SyntaxTreeData>) -> Tree {
+        Tree {
+            ptr: ts_tree_copy(raw),
+        }
+    }
+
     pub fn root_node(&self) -> Node<'_> {
         unsafe { Node::from_raw(ts_tree_root_node(self.ptr)).unwrap() }
     }
@@ -31,6 +46,93 @@ impl Tree {
     pub fn walk(&self) -> TreeCursor<'_> {
         self.root_node().walk()
     }
+
+    /// Compare this old edited syntax tree to a new syntax tree representing the same
+    /// document, returning the ranges whose syntactic structure has changed.
+    ///
+    /// For this to work correctly, this tree must have been edited such that its
+    /// ranges match up to `new_tree`. Generally you'll want to call this method right
+    /// after [`crate::Parser::parse`] with the old tree that was passed to `parse`.
+    ///
+    /// The returned ranges indicate areas where the hierarchical structure of syntax
+    /// nodes (from root to leaf) has changed between the old and new trees. Characters
+    /// outside these ranges have identical ancestor nodes in both trees. Note that the
+    /// ranges may be slightly larger than the exact changed areas.
+    pub fn changed_ranges(&self, new_tree: &Tree) -> Vec<Range> {
+        unsafe {
+            let mut len = 0;
+            let ptr = ts_tree_get_changed_ranges(self.ptr, new_tree.ptr, &mut len);
+            take_ranges(ptr, len)
+        }
+    }
+
+    /// Get the included ranges that were used to parse this syntax tree.
+    ///
+    /// See [`crate::Parser::set_included_ranges`].
+    pub fn included_ranges(&self) -> Vec<Range> {
+        unsafe {
+            let mut len = 0;
+            let ptr = ts_tree_included_ranges(self.ptr, &mut len);
+            take_ranges(ptr, len)
+        }
+    }
+
+    /// Iterate over all `ERROR` and `MISSING` nodes in this tree in document order.
+    ///
+    /// Only subtrees which contain errors are visited, so this is cheap for trees
+    /// that parsed cleanly. `ERROR` nodes are reported as a whole, the iterator
+    /// does not descend into them.
+    pub fn errors(&self) -> SyntaxErrors<'_> {
+        SyntaxErrors {
+            cursor: self.walk(),
+            done: false,
+        }
+    }
+
+    /// Write a graph describing this syntax tree in the DOT language to the
+    /// given file descriptor. The file descriptor is not closed.
+    #[cfg(unix)]
+    pub fn print_dot_graph(&self, fd: &impl AsFd) {
+        unsafe { ts_tree_print_dot_graph(self.ptr, fd.as_fd().as_raw_fd()) }
+    }
+
+    /// Write a graph describing this syntax tree in the DOT language to the
+    /// given sink.
+    ///
+    /// The C library can only write to file descriptors so the graph is
+    /// buffered in a temporary file first.
+    #[cfg(unix)]
+    pub fn write_dot_graph(&self, sink: &mut impl io::Write) -> io::Result<()> {
+        use std::io::Seek;
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "tree-house-{}-{}.dot",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // The file stays accessible through the open handle.
+        fs::remove_file(&path)?;
+        self.print_dot_graph(&file);
+        file.rewind()?;
+        io::copy(&mut file, sink)?;
+        Ok(())
+    }
+}
+
+/// Copies a range array that was allocated by tree-sitter into a `Vec` and frees it.
+unsafe fn take_ranges(ptr: *mut Range, len: u32) -> Vec<Range> {
+    if ptr.is_null() {
+        return Vec::new();
+    }
+    let ranges = slice::from_raw_parts(ptr, len as usize).to_vec();
+    ts_current_free(ptr.cast());
+    ranges
 }
 
 impl fmt::Debug for Tree {
@@ -58,6 +160,104 @@ impl Clone for Tree {
 unsafe impl Send for Tree {}
 unsafe impl Sync for Tree {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxErrorKind {
+    /// The parser skipped over text it could not make sense of and wrapped it
+    /// in an `ERROR` node.
+    Error,
+    /// The parser inserted a zero-width `MISSING` node to recover from the error.
+    Missing,
+}
+
+/// A single syntax error found in a [`Tree`]. See [`Tree::errors`].
+#[derive(Debug, Clone)]
+pub struct SyntaxError<'tree> {
+    pub kind: SyntaxErrorKind,
+    /// The `ERROR` or `MISSING` node itself.
+    pub node: Node<'tree>,
+}
+
+impl<'tree> SyntaxError<'tree> {
+    /// The range of source text covered by the error.
+    ///
+    /// For [`SyntaxErrorKind::Missing`] errors this range is empty.
+    pub fn range(&self) -> Range {
+        self.node.range()
+    }
+
+    /// The closest named ancestor of the error that is not an error itself.
+    pub fn enclosing_node(&self) -> Option<Node<'tree>> {
+        let mut node = self.node.parent()?;
+        while !node.is_named() || node.is_error() {
+            node = node.parent()?;
+        }
+        Some(node)
+    }
+
+    /// The kinds of the visible nodes the parser would have accepted at the
+    /// position of the error.
+    ///
+    /// For `MISSING` nodes this is the kind of the node that the parser
+    /// inserted. For `ERROR` nodes all symbols which are valid in the parse
+    /// state of the first token within the error are returned.
+    pub fn expected(&self) -> Vec<&'tree str> {
+        if self.kind == SyntaxErrorKind::Missing {
+            return vec![self.node.kind()];
+        }
+        let mut leaf = self.node.clone();
+        while let Some(child) = leaf.child(0) {
+            leaf = child;
+        }
+        let grammar = self.node.grammar();
+        let Some(lookahead) = LookaheadIterator::new(grammar, leaf.parse_state()) else {
+            return Vec::new();
+        };
+        let mut expected: Vec<_> = lookahead
+            .filter(|&symbol| symbol != u16::MAX && grammar.node_kind_is_visible(symbol))
+            .filter_map(|symbol| grammar.node_kind_for_id(symbol))
+            .collect();
+        expected.sort_unstable();
+        expected.dedup();
+        expected
+    }
+}
+
+/// An iterator over the syntax errors in a [`Tree`]. See [`Tree::errors`].
+pub struct SyntaxErrors<'tree> {
+    cursor: TreeCursor<'tree>,
+    done: bool,
+}
+
+impl<'tree> Iterator for SyntaxErrors<'tree> {
+    type Item = SyntaxError<'tree>;
+
+    fn next(&mut self) -> Option<SyntaxError<'tree>> {
+        while !self.done {
+            let node = self.cursor.node();
+            let kind = if node.is_error() {
+                Some(SyntaxErrorKind::Error)
+            } else if node.is_missing() {
+                Some(SyntaxErrorKind::Missing)
+            } else {
+                None
+            };
+            if kind.is_none() && node.has_error() && self.cursor.goto_first_child() {
+                continue;
+            }
+            while !self.cursor.goto_next_sibling() {
+                if !self.cursor.goto_parent() {
+                    self.done = true;
+                    break;
+                }
+            }
+            if let Some(kind) = kind {
+                return Some(SyntaxError { kind, node });
+            }
+        }
+        None
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct InputEdit {
@@ -95,4 +295,26 @@ extern "C" {
     /// You must describe the edit both in terms of byte offsets and in terms of
     /// row/column coordinates.
     fn ts_tree_edit(self_: NonNull<SyntaxTreeData>, edit: &InputEdit);
+    /// Get the array of included ranges that was used to parse the syntax tree.
+    ///
+    /// The returned pointer must be freed by the caller.
+    fn ts_tree_included_ranges(self_: NonNull<SyntaxTreeData>, length: &mut u32) -> *mut Range;
+    /// Compare an old edited syntax tree to a new syntax tree representing the same
+    /// document, returning an array of ranges whose syntactic structure has changed.
+    ///
+    /// The returned array is allocated using `malloc` and the caller is responsible
+    /// for freeing it using `free`. The length of the array will be written to the
+    /// given `length` pointer.
+    fn ts_tree_get_changed_ranges(
+        old_tree: NonNull<SyntaxTreeData>,
+        new_tree: NonNull<SyntaxTreeData>,
+        length: &mut u32,
+    ) -> *mut Range;
+    /// Write a DOT graph describing the syntax tree to the given file.
+    #[cfg(unix)]
+    fn ts_tree_print_dot_graph(self_: NonNull<SyntaxTreeData>, file_descriptor: std::ffi::c_int);
+
+    /// The deallocation function used by tree-sitter. Memory returned from the C library which
+    /// the caller is responsible for freeing must be freed with this function.
+    static ts_current_free: unsafe extern "C" fn(ptr: *mut c_void);
 }
@@ -5,7 +5,7 @@ use std::marker::PhantomData;
 use std::{fmt, mem};
 
 use crate::node::NodeRaw;
-use crate::{Node, Tree};
+use crate::{FieldId, Node, Point, Tree};
 
 thread_local! {
     static CACHE: Cell<Option<TreeCursorGuard>> = const { Cell::new(None) };
@@ -76,6 +76,51 @@ impl<'tree> TreeCursor<'tree> {
         }
     }
 
+    /// Move this cursor to the first child of its current node that extends
+    /// beyond the given row/column position.
+    ///
+    /// Returns the index of the child node if one was found.
+    pub fn goto_first_child_for_point(&mut self, point: Point) -> Option<u32> {
+        match unsafe { ts_tree_cursor_goto_first_child_for_point(&mut self.inner, point) } {
+            -1 => None,
+            n => Some(n as u32),
+        }
+    }
+
+    /// Move the cursor to the `index`th descendant (in pre-order) of the node the cursor
+    /// was created with. Zero is the original node itself.
+    ///
+    /// Together with [`TreeCursor::descendant_index`] this allows to save and restore the
+    /// position of the cursor.
+    pub fn goto_descendant(&mut self, index: u32) {
+        unsafe { ts_tree_cursor_goto_descendant(&mut self.inner, index) }
+    }
+
+    /// Get the index of the current node out of all descendants (in pre-order) of the node
+    /// the cursor was created with.
+    pub fn descendant_index(&self) -> u32 {
+        unsafe { ts_tree_cursor_current_descendant_index(&self.inner) }
+    }
+
+    /// Get the depth of the current node relative to the node the cursor was created
+    /// with.
+    pub fn depth(&self) -> u32 {
+        unsafe { ts_tree_cursor_current_depth(&self.inner) }
+    }
+
+    /// Re-initialize the cursor to start at `node`, which becomes the new root of the
+    /// cursor.
+    pub fn reset(&mut self, node: &Node<'tree>) {
+        unsafe { ts_tree_cursor_reset(&mut self.inner, node.as_raw()) }
+    }
+
+    /// Move the cursor to the position of `other`. Unlike [`TreeCursor::reset`] this keeps
+    /// the parents of the current node (so [`TreeCursor::goto_parent`] still works) and
+    /// unlike [`Clone::clone`] it reuses the memory of this cursor.
+    pub fn reset_to(&mut self, other: &TreeCursor<'tree>) {
+        unsafe { ts_tree_cursor_reset_to(&mut self.inner, &other.inner) }
+    }
+
     pub fn node(&self) -> Node<'tree> {
         unsafe { Node::from_raw(ts_tree_cursor_current_node(&self.inner)).unwrap_unchecked() }
     }
@@ -86,6 +131,13 @@ impl<'tree> TreeCursor<'tree> {
             (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
         }
     }
+
+    /// Get the numerical field id of this cursor's current node.
+    ///
+    /// See also [`TreeCursor::field_name`].
+    pub fn field_id(&self) -> Option<FieldId> {
+        unsafe { FieldId::new(ts_tree_cursor_current_field_id(&self.inner)) }
+    }
 }
 
 impl fmt::Debug for TreeCursorRaw {
@@ -123,20 +175,16 @@ extern "C" {
     fn ts_tree_cursor_delete(self_: *mut TreeCursorRaw);
     /// Re-initialize a tree cursor to start at a different node.
     fn ts_tree_cursor_reset(self_: *mut TreeCursorRaw, node: NodeRaw);
-    // /// Re-initialize a tree cursor to the same position as another cursor.
-    // /// Unlike [`ts_tree_cursor_reset`], this will not lose parent information and
-    // /// allows reusing already created cursors.
-    // fn ts_tree_cursor_reset_to(dst: *mut TreeCursorRaw, src: *const TreeCursorRaw);
+    /// Re-initialize a tree cursor to the same position as another cursor.
+    /// Unlike [`ts_tree_cursor_reset`], this will not lose parent information and
+    /// allows reusing already created cursors.
+    fn ts_tree_cursor_reset_to(dst: *mut TreeCursorRaw, src: *const TreeCursorRaw);
     /// Get the tree cursor's current node.
     fn ts_tree_cursor_current_node(self_: *const TreeCursorRaw) -> NodeRaw;
-    // /// Get the field name of the tree cursor's current node.
-    // /// This returns `NULL` if the current node doesn't have a field.
-    // /// See also [`ts_node_child_by_field_name`].
-    // fn ts_tree_cursor_current_field_name(self_: *const TreeCursorRaw) -> *const raw::c_char;
-    // /// Get the field id of the tree cursor's current node.
-    // /// This returns zero if the current node doesn't have a field.
-    // /// See also [`ts_node_child_by_field_id`], [`ts_language_field_id_for_name`].
-    // fn ts_tree_cursor_current_field_id(self_: *const TreeCursorRaw) -> TSFieldId;
+    /// Get the field id of the tree cursor's current node.
+    /// This returns zero if the current node doesn't have a field.
+    /// See also `ts_node_child_by_field_id`, `ts_language_field_id_for_name`.
+    fn ts_tree_cursor_current_field_id(self_: *const TreeCursorRaw) -> u16;
     /// Move the cursor to the parent of its current node.
     /// This returns `true` if the cursor successfully moved, and returns `false`
     /// if there was no parent node (the cursor was already on the root node).
@@ -164,7 +212,6 @@ extern "C" {
     /// because it needs to iterate through all the children to compute the child's
     /// position.
     fn ts_tree_cursor_goto_last_child(self_: *mut TreeCursorRaw) -> bool;
-    /*
     /// Move the cursor to the node that is the nth descendant of
     /// the original node that the cursor was constructed with, where
     /// zero represents the original node itself.
@@ -175,12 +222,15 @@ extern "C" {
     /// Get the depth of the cursor's current node relative to the original
     /// node that the cursor was constructed with.
     fn ts_tree_cursor_current_depth(self_: *const TreeCursorRaw) -> u32;
-    */
     /// Move the cursor to the first child of its current node that extends beyond
     /// the given byte offset or point.
     /// This returns the index of the child node if one was found, and returns -1
     /// if no such child was found.
     fn ts_tree_cursor_goto_first_child_for_byte(self_: *mut TreeCursorRaw, goal_byte: u32) -> i64;
+    fn ts_tree_cursor_goto_first_child_for_point(
+        self_: *mut TreeCursorRaw,
+        goal_point: Point,
+    ) -> i64;
     fn ts_tree_cursor_copy(cursor: *const TreeCursorRaw) -> TreeCursorRaw;
     /// Get the field name of the tree cursor's curren tnode.
     ///
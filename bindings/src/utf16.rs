@@ -0,0 +1,73 @@
+use std::ops::Range;
+use std::{mem, slice};
+
+use regex_cursor::Cursor;
+
+use crate::{ChunkedText, Input, InputEncoding};
+
+impl<'a> ChunkedText<'a> for &'a [u16] {
+    fn len_bytes(self) -> usize {
+        mem::size_of_val(self)
+    }
+
+    fn chunk_at_byte(self, _byte_idx: usize) -> (&'a [u8], usize) {
+        // SAFETY: `u16` has no padding and a stricter alignment than `u8`.
+        let bytes = unsafe { slice::from_raw_parts(self.as_ptr().cast(), mem::size_of_val(self)) };
+        (bytes, 0)
+    }
+
+    fn encoding(self) -> InputEncoding {
+        InputEncoding::UTF16_NATIVE
+    }
+}
+
+/// Decodes the UTF-16 text in `range` of `input` into a UTF-8 string. Unpaired surrogates
+/// are replaced with `U+FFFD`.
+pub(crate) fn decode_utf16<I: Input>(input: &mut I, range: Range<u32>) -> String {
+    let big_endian = input.encoding() == InputEncoding::Utf16BE;
+    let bytes = input_bytes(input, range);
+    utf16_chars(&bytes, big_endian).map(|(c, _)| c).collect()
+}
+
+/// Decodes UTF-16 `bytes` into chars together with the number of bytes each char was
+/// encoded with. Unpaired surrogates are replaced with `U+FFFD`.
+pub(crate) fn utf16_chars(
+    bytes: &[u8],
+    big_endian: bool,
+) -> impl Iterator<Item = (char, u32)> + '_ {
+    let code_units = bytes.chunks_exact(2).map(move |unit| {
+        let unit = [unit[0], unit[1]];
+        if big_endian {
+            u16::from_be_bytes(unit)
+        } else {
+            u16::from_le_bytes(unit)
+        }
+    });
+    char::decode_utf16(code_units).map(|c| match c {
+        Ok(c) => (c, 2 * c.len_utf16() as u32),
+        Err(_) => (char::REPLACEMENT_CHARACTER, 2),
+    })
+}
+
+/// Copies the bytes in `range` of `input`, which may span multiple chunks.
+pub(crate) fn input_bytes<I: Input>(input: &mut I, range: Range<u32>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(range.len());
+    let cursor = input.cursor_at(range.start);
+    let range = range.start as usize..range.end as usize;
+    loop {
+        let chunk_start = cursor.offset();
+        let chunk = cursor.chunk();
+        let start = range.start.max(chunk_start) - chunk_start;
+        let end = range
+            .end
+            .min(chunk_start + chunk.len())
+            .saturating_sub(chunk_start);
+        if start < end {
+            bytes.extend_from_slice(&chunk[start..end]);
+        }
+        if chunk_start + chunk.len() >= range.end || !cursor.advance() {
+            break;
+        }
+    }
+    bytes
+}
@@ -16,14 +16,20 @@ impl flags::Build {
             index: PathBuf::new(),
             verbose: self.verbose,
         };
-        if let Some(grammar) = self.grammar {
-            skidder::build_grammar(&config, &grammar, self.force)?;
-        } else {
-            skidder::build_all_grammars(
-                &config,
-                self.force,
-                self.threads.and_then(NonZeroUsize::new),
-            )?;
+        let threads = self.threads.and_then(NonZeroUsize::new);
+        match (self.grammar, self.static_lib) {
+            (Some(grammar), false) => {
+                skidder::build_grammar(&config, &grammar, self.force)?;
+            }
+            (Some(grammar), true) => {
+                skidder::build_grammar_static(&config, &grammar, self.force)?;
+            }
+            (None, false) => {
+                skidder::build_all_grammars(&config, self.force, threads)?;
+            }
+            (None, true) => {
+                skidder::build_all_grammars_static(&config, self.force, threads)?;
+            }
         }
         Ok(())
     }
@@ -19,6 +19,9 @@ xflags::xflags! {
         }
         cmd build {
             optional --verbose
+            /// Build static archives which can be linked into a binary
+            /// instead of shared libraries
+            optional --static-lib
             optional -j, --threads threads: usize
             optional -f, --force
             required repo: PathBuf
@@ -69,6 +72,7 @@ pub struct Build {
     pub grammar: Option<String>,
 
     pub verbose: bool,
+    pub static_lib: bool,
     pub threads: Option<usize>,
     pub force: bool,
 }
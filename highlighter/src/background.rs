@@ -0,0 +1,332 @@
+//! Reparsing a [`Syntax`] on a background thread.
+//!
+//! [`Syntax::update`] mutates the syntax in place and blocks until all layers are parsed.
+//! [`SharedSyntax`] instead holds an immutable snapshot behind an [`ArcSwap`]. Updates are
+//! applied to a copy of the latest snapshot on a worker thread and the result is published
+//! atomically once parsing finished.
+//!
+//! Copying a syntax copies the data of every layer: the tree-sitter trees are reference
+//! counted but the ranges, injections, changed ranges and locals are cloned. The cost of an
+//! update therefore includes a copy which grows with the number of layers and definitions,
+//! although it is paid on the worker thread and is usually small compared to parsing.
+//!
+//! Readers like the [`Highlighter`](crate::highlighter::Highlighter) and
+//! [`QueryIter`](crate::query_iter::QueryIter) borrow a snapshot returned by
+//! [`SharedSyntax::load`] and are not affected by updates that finish while they are running.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use arc_swap::ArcSwap;
+use tree_sitter::{ChunkedText, InputEdit, Point};
+
+use crate::{Error, LanguageLoader, MaybeSend, ParseLimit, Syntax, UpdateReport};
+
+type UpdateFn = Box<dyn FnOnce(&Syntax, &[InputEdit]) -> Result<Syntax, Error> + Send>;
+
+struct Job {
+    edits: Vec<InputEdit>,
+    update: UpdateFn,
+    result: Sender<Result<UpdateReport, Error>>,
+}
+
+/// A [`Syntax`] which is reparsed on a background thread while readers keep using the
+/// previous snapshot.
+///
+/// Updates are queued and applied one after another on a single worker thread (which is
+/// spawned by the first update) so the edits passed to
+/// [`update_in_background`](Self::update_in_background) must be relative to the source text of
+/// the previous update, just like for consecutive calls to [`Syntax::update`].
+pub struct SharedSyntax {
+    snapshot: Arc<ArcSwap<Syntax>>,
+    worker: OnceLock<Sender<Job>>,
+}
+
+impl fmt::Debug for SharedSyntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSyntax")
+            .field("snapshot", &self.snapshot.load())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedSyntax {
+    pub fn new(syntax: Syntax) -> Self {
+        Self {
+            snapshot: Arc::new(ArcSwap::from_pointee(syntax)),
+            worker: OnceLock::new(),
+        }
+    }
+
+    /// Returns the most recently published snapshot.
+    ///
+    /// The snapshot never changes: updates which finish later publish a new snapshot instead.
+    pub fn load(&self) -> Arc<Syntax> {
+        self.snapshot.load_full()
+    }
+
+    /// Queues an update of the syntax with the given `edits` and the (already edited) `source`
+    /// text and returns immediately.
+    ///
+    /// The update is applied to a copy of the latest snapshot and the result is published once
+    /// parsing succeeded. If parsing fails, for example because the root layer exceeded the
+    /// `limit` or the update was [cancelled](BackgroundUpdate::cancel), the current snapshot
+    /// stays published. The edits of a failed update are combined with the edits of the next
+    /// update so that it still reparses the published trees incrementally.
+    ///
+    /// The `loader` is moved to the worker thread, typically it is an `Arc` which is shared with
+    /// the rest of the application.
+    pub fn update_in_background<T, L>(
+        &self,
+        source: T,
+        limit: impl Into<ParseLimit>,
+        edits: Vec<InputEdit>,
+        loader: L,
+    ) -> BackgroundUpdate
+    where
+        T: Send + 'static,
+        for<'a> &'a T: ChunkedText<'a> + MaybeSend,
+        L: Deref + Send + 'static,
+        L::Target: LanguageLoader + Sized,
+    {
+        let mut limit = limit.into();
+        let cancellation_flag = limit
+            .cancellation_flag
+            .get_or_insert_with(Default::default)
+            .clone();
+        let update: UpdateFn = Box::new(move |snapshot, edits| {
+            let mut syntax = snapshot.clone();
+            syntax.update(&source, limit, edits, &*loader)?;
+            Ok(syntax)
+        });
+        let (sender, result) = mpsc::channel();
+        // If the worker panicked the job is dropped which disconnects the result channel.
+        let _ = self.worker().send(Job {
+            edits,
+            update,
+            result: sender,
+        });
+        BackgroundUpdate {
+            result,
+            cancellation_flag,
+            finished: false,
+        }
+    }
+
+    fn worker(&self) -> &Sender<Job> {
+        self.worker.get_or_init(|| {
+            let (sender, jobs) = mpsc::channel::<Job>();
+            let snapshot = self.snapshot.clone();
+            thread::Builder::new()
+                .name("tree-house-parser".to_owned())
+                .spawn(move || {
+                    // The edits of failed updates: they were never applied to the published
+                    // trees so they are applied together with the edits of the next update.
+                    let mut pending_edits = Vec::new();
+                    for job in jobs {
+                        let edits = compose_edits(&pending_edits, &job.edits);
+                        let res = (job.update)(&snapshot.load(), &edits);
+                        pending_edits = if res.is_err() { edits } else { Vec::new() };
+                        let res = res.map(|syntax| {
+                            let report = syntax.report();
+                            snapshot.store(Arc::new(syntax));
+                            report
+                        });
+                        let _ = job.result.send(res);
+                    }
+                })
+                .expect("failed to spawn the background parsing thread");
+            sender
+        })
+    }
+}
+
+/// A handle to an update queued with [`SharedSyntax::update_in_background`].
+///
+/// Dropping the handle does not cancel the update.
+#[derive(Debug)]
+pub struct BackgroundUpdate {
+    result: Receiver<Result<UpdateReport, Error>>,
+    cancellation_flag: Arc<AtomicBool>,
+    finished: bool,
+}
+
+impl BackgroundUpdate {
+    /// Cancels the update. This is useful when the document was edited again and the result of
+    /// this update would be replaced immediately.
+    ///
+    /// An update which already finished is not affected.
+    pub fn cancel(&self) {
+        self.cancellation_flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the result of the update if it finished, without blocking.
+    ///
+    /// The result is only returned once, later calls return `None`.
+    pub fn try_wait(&mut self) -> Option<Result<UpdateReport, Error>> {
+        if self.finished {
+            return None;
+        }
+        let res = match self.result.try_recv() {
+            Ok(res) => res,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(Error::Unknown),
+        };
+        self.finished = true;
+        Some(res)
+    }
+
+    /// Blocks until the update finished. Once this returns `Ok` the new syntax is published.
+    /// The returned report lists the layers of the new syntax which are stale.
+    ///
+    /// Returns `None` if the result was already returned by [`try_wait`](Self::try_wait).
+    pub fn wait(self) -> Option<Result<UpdateReport, Error>> {
+        if self.finished {
+            return None;
+        }
+        Some(self.result.recv().unwrap_or(Err(Error::Unknown)))
+    }
+}
+
+/// Combines the edits of two consecutive updates into the edits of a single update, so that
+/// the edits of a failed update can be applied together with the edits of the next one.
+///
+/// `first` turns the original text into an intermediate text and `second` turns the
+/// intermediate text into the final text. The returned edits turn the original text into the
+/// final text. Edits of `second` which overlap or touch text inserted by `first` are merged
+/// with those edits.
+pub(crate) fn compose_edits(first: &[InputEdit], second: &[InputEdit]) -> Vec<InputEdit> {
+    if first.is_empty() || second.is_empty() {
+        return if first.is_empty() { second } else { first }.to_vec();
+    }
+    let first = spans(first);
+    let second = spans(second);
+    // The replaced ranges of both updates in the coordinates of the intermediate text.
+    let mut ranges: Vec<_> = first
+        .iter()
+        .map(|span| (span.new_start, span.new_end))
+        .chain(second.iter().map(|span| (span.old_start, span.old_end)))
+        .collect();
+    ranges.sort_by_key(|(start, _)| start.byte);
+    let mut ranges = ranges.into_iter().peekable();
+    let mut edits = Vec::new();
+    while let Some((start, mut end)) = ranges.next() {
+        while let Some((_, next_end)) =
+            ranges.next_if(|(next_start, _)| next_start.byte <= end.byte)
+        {
+            if next_end.byte > end.byte {
+                end = next_end;
+            }
+        }
+        // Map the bounds of the merged range to the original text and to the final text. A
+        // bound which is not the bound of an edit lies in text which the edits left unchanged.
+        let old_start = first
+            .iter()
+            .find(|span| span.new_start.byte == start.byte)
+            .map_or_else(|| map_back(&first, start), |span| span.old_start);
+        let old_end = first
+            .iter()
+            .rfind(|span| span.new_end.byte == end.byte)
+            .map_or_else(|| map_back(&first, end), |span| span.old_end);
+        let new_start = second
+            .iter()
+            .find(|span| span.old_start.byte == start.byte)
+            .map_or_else(|| map_forward(&second, start), |span| span.new_start);
+        let new_end = second
+            .iter()
+            .rfind(|span| span.old_end.byte == end.byte)
+            .map_or_else(|| map_forward(&second, end), |span| span.new_end);
+        let new_end = translate(new_end, new_start, old_start);
+        edits.push(InputEdit {
+            start_byte: old_start.byte,
+            old_end_byte: old_end.byte,
+            new_end_byte: new_end.byte,
+            start_point: old_start.point,
+            old_end_point: old_end.point,
+            new_end_point: new_end.point,
+        });
+    }
+    edits
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    byte: u32,
+    point: Point,
+}
+
+/// An edit with its replaced range in the old text and its replacement in the new text.
+#[derive(Debug)]
+struct Span {
+    old_start: Position,
+    old_end: Position,
+    new_start: Position,
+    new_end: Position,
+}
+
+fn spans(edits: &[InputEdit]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let old_start = Position {
+            byte: edit.start_byte,
+            point: edit.start_point,
+        };
+        let new_start = spans.last().map_or(old_start, |prev| {
+            translate(old_start, prev.old_end, prev.new_end)
+        });
+        // The new end of an edit is relative to its start since the edits are applied in reverse.
+        let new_end = Position {
+            byte: edit.new_end_byte,
+            point: edit.new_end_point,
+        };
+        spans.push(Span {
+            old_start,
+            old_end: Position {
+                byte: edit.old_end_byte,
+                point: edit.old_end_point,
+            },
+            new_start,
+            new_end: translate(new_end, old_start, new_start),
+        });
+    }
+    spans
+}
+
+/// Maps a position of the new text which is not replaced by any of the `spans` to the old text.
+fn map_back(spans: &[Span], pos: Position) -> Position {
+    spans
+        .iter()
+        .rfind(|span| span.new_end.byte <= pos.byte)
+        .map_or(pos, |span| translate(pos, span.new_end, span.old_end))
+}
+
+/// Maps a position of the old text which is not replaced by any of the `spans` to the new text.
+fn map_forward(spans: &[Span], pos: Position) -> Position {
+    spans
+        .iter()
+        .rfind(|span| span.old_end.byte <= pos.byte)
+        .map_or(pos, |span| translate(pos, span.old_end, span.new_end))
+}
+
+/// Moves `pos`, which is after `from`, by the distance between `from` and `to`.
+fn translate(pos: Position, from: Position, to: Position) -> Position {
+    let point = if pos.point.row == from.point.row {
+        Point {
+            row: to.point.row,
+            col: to.point.col + pos.point.col - from.point.col,
+        }
+    } else {
+        Point {
+            row: pos.point.row - from.point.row + to.point.row,
+            col: pos.point.col,
+        }
+    };
+    Position {
+        byte: pos.byte - from.byte + to.byte,
+        point,
+    }
+}
@@ -1,6 +1,6 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
-use tree_sitter::{query, Grammar};
+use tree_sitter::{query, Grammar, PredicateRegistry};
 
 use crate::highlighter::{Highlight, HighlightQuery};
 use crate::injections_query::{InjectionLanguageMarker, InjectionsQuery};
@@ -21,13 +21,36 @@ impl LanguageConfig {
         highlight_query_text: &str,
         injection_query_text: &str,
         local_query_text: &str,
+    ) -> Result<Self, query::ParseError> {
+        Self::with_predicates(
+            grammar,
+            highlight_query_text,
+            injection_query_text,
+            local_query_text,
+            &PredicateRegistry::default(),
+        )
+    }
+
+    /// Create the configuration like [`LanguageConfig::new`] with the custom
+    /// predicates in `registry` evaluated by all queries.
+    pub fn with_predicates(
+        grammar: Grammar,
+        highlight_query_text: &str,
+        injection_query_text: &str,
+        local_query_text: &str,
+        registry: &PredicateRegistry,
     ) -> Result<Self, query::ParseError> {
         // NOTE: the injection queries are parsed first since the local query is passed as-is
         // to `Query::new` in `InjectionsQuery::new`. This ensures that the more readable error
         // bubbles up first if the locals queries have an issue.
-        let injection_query =
-            InjectionsQuery::new(grammar, injection_query_text, local_query_text)?;
-        let highlight_query = HighlightQuery::new(grammar, highlight_query_text, local_query_text)?;
+        let injection_query = InjectionsQuery::with_predicates(
+            grammar,
+            injection_query_text,
+            local_query_text,
+            registry,
+        )?;
+        let highlight_query =
+            HighlightQuery::new(grammar, highlight_query_text, local_query_text, registry)?;
 
         Ok(Self {
             grammar,
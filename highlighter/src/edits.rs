@@ -0,0 +1,182 @@
+//! Computing the [`InputEdit`]s which are passed to [`Syntax::update`](crate::Syntax::update).
+//!
+//! The edits of a single update are all expressed in the coordinates of the text *before* the
+//! update, sorted by position and non-overlapping. Their points use the line breaks of ropey
+//! and columns in bytes, which is how tree-sitter counts them.
+
+use std::ops::Range;
+
+#[cfg(feature = "diff")]
+use imara_diff::intern::{InternedInput, TokenSource};
+#[cfg(feature = "diff")]
+use imara_diff::Algorithm;
+#[cfg(feature = "diff")]
+use ropey::iter::Lines;
+use ropey::RopeSlice;
+use tree_sitter::{InputEdit, Point};
+
+/// Returns the edits for replacing the char ranges of `old_text` with the given texts.
+///
+/// The `changes` must be sorted and non-overlapping. Each range is given in chars of
+/// `old_text`, the text before any of the changes were applied, like the changes of a ropey
+/// transaction. Changes which touch are merged into a single edit and changes which don't
+/// change anything are skipped.
+///
+/// # Panics
+///
+/// Panics if the changes are not sorted or overlap, or if a range is out of bounds.
+pub fn edits_for_changes<S: AsRef<str>>(
+    old_text: RopeSlice<'_>,
+    changes: impl IntoIterator<Item = (Range<usize>, S)>,
+) -> Vec<InputEdit> {
+    let mut edits: Vec<InputEdit> = Vec::new();
+    let mut last_end = 0;
+    for (range, replacement) in changes {
+        assert!(
+            last_end <= range.start && range.start <= range.end,
+            "changes must be sorted and non-overlapping"
+        );
+        last_end = range.end;
+        let replacement = replacement.as_ref();
+        if range.is_empty() && replacement.is_empty() {
+            continue;
+        }
+        push_edit(&mut edits, input_edit(old_text, range, replacement.into()));
+    }
+    edits
+}
+
+/// Returns the edits which turn `old_text` into `new_text`.
+///
+/// The texts are diffed line by line with the histogram algorithm. The common prefix and suffix
+/// of each changed block of lines are excluded from the edit so that edits within a line only
+/// cover the changed characters.
+#[cfg(feature = "diff")]
+pub fn edits_for_diff(old_text: RopeSlice<'_>, new_text: RopeSlice<'_>) -> Vec<InputEdit> {
+    let input = InternedInput::new(RopeLines(old_text), RopeLines(new_text));
+    let mut edits = Vec::new();
+    imara_diff::diff(
+        Algorithm::Histogram,
+        &input,
+        |before: Range<u32>, after: Range<u32>| {
+            let mut old = old_text.line_to_char(before.start as usize)
+                ..old_text.line_to_char(before.end as usize);
+            let mut new = new_text.line_to_char(after.start as usize)
+                ..new_text.line_to_char(after.end as usize);
+            let prefix = common_prefix(old_text.slice(old.clone()), new_text.slice(new.clone()));
+            old.start += prefix;
+            new.start += prefix;
+            let suffix = common_suffix(old_text.slice(old.clone()), new_text.slice(new.clone()));
+            old.end -= suffix;
+            new.end -= suffix;
+            if !old.is_empty() || !new.is_empty() {
+                push_edit(&mut edits, input_edit(old_text, old, new_text.slice(new)));
+            }
+        },
+    );
+    edits
+}
+
+/// Returns the edit for replacing the char `range` of `old_text` with `replacement`.
+fn input_edit(
+    old_text: RopeSlice<'_>,
+    range: Range<usize>,
+    replacement: RopeSlice<'_>,
+) -> InputEdit {
+    let start_byte = old_text.char_to_byte(range.start);
+    let old_end_byte = old_text.char_to_byte(range.end);
+    let start_point = point(old_text, start_byte);
+    InputEdit {
+        start_byte: start_byte as u32,
+        old_end_byte: old_end_byte as u32,
+        new_end_byte: (start_byte + replacement.len_bytes()) as u32,
+        start_point,
+        old_end_point: point(old_text, old_end_byte),
+        new_end_point: end_point(start_point, replacement),
+    }
+}
+
+/// Appends `edit` to the sorted `edits`, merging it into the last edit if they touch.
+fn push_edit(edits: &mut Vec<InputEdit>, edit: InputEdit) {
+    let Some(last) = edits
+        .last_mut()
+        .filter(|last| last.old_end_byte == edit.start_byte)
+    else {
+        edits.push(edit);
+        return;
+    };
+    // Without the text in between `edit` starts where the replacement of `last` ends.
+    let rows = edit.new_end_point.row - edit.start_point.row;
+    last.new_end_point = if rows == 0 {
+        Point {
+            row: last.new_end_point.row,
+            col: last.new_end_point.col + edit.new_end_point.col - edit.start_point.col,
+        }
+    } else {
+        Point {
+            row: last.new_end_point.row + rows,
+            col: edit.new_end_point.col,
+        }
+    };
+    last.new_end_byte += edit.new_end_byte - edit.start_byte;
+    last.old_end_byte = edit.old_end_byte;
+    last.old_end_point = edit.old_end_point;
+}
+
+fn point(text: RopeSlice<'_>, byte: usize) -> Point {
+    let row = text.byte_to_line(byte);
+    Point {
+        row: row as u32,
+        col: (byte - text.line_to_byte(row)) as u32,
+    }
+}
+
+/// Returns the point where `text` ends if it is inserted at `start`.
+fn end_point(start: Point, text: RopeSlice<'_>) -> Point {
+    let rows = text.len_lines() - 1;
+    if rows == 0 {
+        Point {
+            row: start.row,
+            col: start.col + text.len_bytes() as u32,
+        }
+    } else {
+        Point {
+            row: start.row + rows as u32,
+            col: (text.len_bytes() - text.line_to_byte(rows)) as u32,
+        }
+    }
+}
+
+/// Returns the number of chars at the start of both texts which are equal.
+#[cfg(feature = "diff")]
+fn common_prefix(a: RopeSlice<'_>, b: RopeSlice<'_>) -> usize {
+    a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count()
+}
+
+/// Returns the number of chars at the end of both texts which are equal.
+#[cfg(feature = "diff")]
+fn common_suffix(a: RopeSlice<'_>, b: RopeSlice<'_>) -> usize {
+    a.chars_at(a.len_chars())
+        .reversed()
+        .zip(b.chars_at(b.len_chars()).reversed())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// The lines of a rope, used as the tokens of the diff.
+#[cfg(feature = "diff")]
+struct RopeLines<'a>(RopeSlice<'a>);
+
+#[cfg(feature = "diff")]
+impl<'a> TokenSource for RopeLines<'a> {
+    type Token = RopeSlice<'a>;
+    type Tokenizer = Lines<'a>;
+
+    fn tokenize(&self) -> Self::Tokenizer {
+        self.0.lines()
+    }
+
+    fn estimate_tokens(&self) -> u32 {
+        self.0.len_lines() as u32
+    }
+}
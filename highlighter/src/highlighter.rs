@@ -1,4 +1,3 @@
-use std::borrow::Cow;
 use std::fmt;
 use std::mem::replace;
 use std::num::NonZeroU32;
@@ -12,12 +11,11 @@ use crate::query_iter::{MatchedNode, QueryIter, QueryIterEvent, QueryLoader};
 use crate::{Injection, Language, Layer, Syntax};
 use arc_swap::ArcSwap;
 use hashbrown::{HashMap, HashSet};
-use ropey::RopeSlice;
 use tree_sitter::{
     query::{self, InvalidPredicateError, Query, UserPredicate},
     Capture, Grammar,
 };
-use tree_sitter::{Pattern, QueryMatch};
+use tree_sitter::{ChunkedText, Pattern, PredicateRegistry, QueryMatch};
 
 /// Contains the data needed to highlight code written in a particular language.
 ///
@@ -37,6 +35,7 @@ impl HighlightQuery {
         grammar: Grammar,
         highlight_query_text: &str,
         local_query_text: &str,
+        registry: &PredicateRegistry,
     ) -> Result<Self, query::ParseError> {
         // Concatenate the highlights and locals queries.
         let mut query_source =
@@ -45,28 +44,29 @@ impl HighlightQuery {
         query_source.push_str(local_query_text);
 
         let mut non_local_patterns = HashSet::new();
-        let mut query = Query::new(grammar, &query_source, |pattern, predicate| {
-            match predicate {
-                // Allow the `(#set! local.scope-inherits <bool>)` property to be parsed.
-                // This information is not used by this query though, it's used in the
-                // injection query instead.
-                UserPredicate::SetProperty {
-                    key: "local.scope-inherits",
-                    ..
-                } => (),
-                // TODO: `(#is(-not)? local)` applies to the entire pattern. Ideally you
-                // should be able to supply capture(s?) which are each checked.
-                UserPredicate::IsPropertySet {
-                    negate: true,
-                    key: "local",
-                    val: None,
-                } => {
-                    non_local_patterns.insert(pattern);
+        let mut query =
+            Query::with_predicates(grammar, &query_source, registry, |pattern, predicate| {
+                match predicate {
+                    // Allow the `(#set! local.scope-inherits <bool>)` property to be parsed.
+                    // This information is not used by this query though, it's used in the
+                    // injection query instead.
+                    UserPredicate::SetProperty {
+                        key: "local.scope-inherits",
+                        ..
+                    } => (),
+                    // TODO: `(#is(-not)? local)` applies to the entire pattern. Ideally you
+                    // should be able to supply capture(s?) which are each checked.
+                    UserPredicate::IsPropertySet {
+                        negate: true,
+                        key: "local",
+                        val: None,
+                    } => {
+                        non_local_patterns.insert(pattern);
+                    }
+                    _ => return Err(InvalidPredicateError::unknown(predicate)),
                 }
-                _ => return Err(InvalidPredicateError::unknown(predicate)),
-            }
-            Ok(())
-        })?;
+                Ok(())
+            })?;
 
         // The highlight query only cares about local.reference captures. All scope and definition
         // captures can be disabled.
@@ -155,8 +155,8 @@ pub struct LayerData {
     dormant_highlights: Vec<HighlightedNode>,
 }
 
-pub struct Highlighter<'a, 'tree, Loader: LanguageLoader> {
-    query: QueryIter<'a, 'tree, HighlightQueryLoader<&'a Loader>, ()>,
+pub struct Highlighter<'a, 'tree, Text: ChunkedText<'a>, Loader: LanguageLoader> {
+    query: QueryIter<'a, 'tree, Text, HighlightQueryLoader<&'a Loader>, ()>,
     next_query_event: Option<QueryIterEvent<'tree, ()>>,
     active_highlights: Vec<HighlightedNode>,
     next_highlight_end: u32,
@@ -205,10 +205,12 @@ pub enum HighlightEvent {
     Push,
 }
 
-impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
+impl<'a, 'tree: 'a, Text: ChunkedText<'a>, Loader: LanguageLoader>
+    Highlighter<'a, 'tree, Text, Loader>
+{
     pub fn new(
         syntax: &'tree Syntax,
-        src: RopeSlice<'a>,
+        src: Text,
         loader: &'a Loader,
         range: impl RangeBounds<u32>,
     ) -> Self {
@@ -331,7 +333,7 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
     }
 
     fn start_highlight(&mut self, node: MatchedNode, first_highlight: &mut bool) {
-        let range = node.node.byte_range();
+        let range = node.range.clone();
         // `<QueryIter as Iterator>::next` skips matches with empty ranges.
         debug_assert!(
             !range.is_empty(),
@@ -345,11 +347,10 @@ impl<'a, 'tree: 'a, Loader: LanguageLoader> Highlighter<'a, 'tree, Loader> {
         let highlight = if Some(node.capture) == config.highlight_query.local_reference_capture {
             // If this capture was a `@local.reference` from the locals queries, look up the
             // text of the node in the current locals cursor and use that highlight.
-            let text: Cow<str> = self
+            let text = self
                 .query
                 .source()
-                .byte_slice(range.start as usize..range.end as usize)
-                .into();
+                .byte_text(range.start as usize..range.end as usize);
             let Some(definition) = self
                 .query
                 .syntax()
@@ -399,11 +400,11 @@ impl<'a, T: LanguageLoader> QueryLoader<'a> for HighlightQueryLoader<&'a T> {
             .map(|config| &config.highlight_query.query)
     }
 
-    fn are_predicates_satisfied(
+    fn are_predicates_satisfied<'s>(
         &self,
         lang: Language,
         mat: &QueryMatch<'_, '_>,
-        source: RopeSlice<'_>,
+        source: impl ChunkedText<'s>,
         locals_cursor: &ScopeCursor<'_>,
     ) -> bool {
         let highlight_query = &self
@@ -425,9 +426,7 @@ impl<'a, T: LanguageLoader> QueryLoader<'a> for HighlightQueryLoader<&'a T> {
         {
             let has_local_reference = mat.matched_nodes().any(|n| {
                 let range = n.node.byte_range();
-                let text: Cow<str> = source
-                    .byte_slice(range.start as usize..range.end as usize)
-                    .into();
+                let text = source.byte_text(range.start as usize..range.end as usize);
                 locals_cursor
                     .locals
                     .lookup_reference(locals_cursor.current_scope(), &text)
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::cmp::Reverse;
 use std::iter::{self, Peekable};
 use std::mem::take;
@@ -7,7 +8,6 @@ use arc_swap::ArcSwap;
 use hashbrown::{HashMap, HashSet};
 use once_cell::sync::Lazy;
 use regex_cursor::engines::meta::Regex;
-use ropey::RopeSlice;
 
 use crate::config::{LanguageConfig, LanguageLoader};
 use crate::highlighter::Highlight;
@@ -16,9 +16,16 @@ use crate::parse::LayerUpdateFlags;
 use crate::{Injection, Language, Layer, LayerData, Range, Syntax, TREE_SITTER_MATCH_LIMIT};
 use tree_sitter::{
     query::{self, InvalidPredicateError, UserPredicate},
-    Capture, Grammar, InactiveQueryCursor, MatchedNodeIdx, Node, Pattern, Query, QueryMatch,
+    Capture, ChunkedInput, ChunkedText, Grammar, InactiveQueryCursor, MatchedNodeIdx, Node,
+    Pattern, PredicateRegistry, Query, QueryMatch,
 };
 
+/// Allows the iterator returned by `InjectionsQuery::execute` to capture the lifetime of the
+/// source text.
+// TODO: replace with `use<..>` bounds once the MSRV allows it.
+trait Captures<'a> {}
+impl<T: ?Sized> Captures<'_> for T {}
+
 const SHEBANG: &str = r"#!\s*(?:\S*[/\\](?:env\s+(?:\-\S+\s+)*)?)?([^\s\.\d]+)";
 static SHEBANG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(SHEBANG).unwrap());
 
@@ -35,7 +42,11 @@ pub struct InjectionProperties {
 /// For example if a query sets a property `(#set! injection.language "rust")` then the loader
 /// should load the Rust language. Alternatively the loader might be asked to load a language
 /// based on some text in the document, for example a markdown code fence language name.
-#[derive(Debug, Clone, Copy)]
+///
+/// Text captured from the document is borrowed when it is contained in a single chunk of the
+/// source. It is owned when it spans multiple chunks or was rewritten by a directive like
+/// `#gsub!`.
+#[derive(Debug, Clone)]
 pub enum InjectionLanguageMarker<'a> {
     /// The language is specified by name in the injection query itself.
     ///
@@ -47,9 +58,9 @@ pub enum InjectionLanguageMarker<'a> {
     /// This is slightly different than the `ExactName` variant: within a document you might
     /// specify Markdown as "md" or "markdown" for example. The loader should look up the language
     /// name by longest matching regex.
-    Match(RopeSlice<'a>),
-    Filename(RopeSlice<'a>),
-    Shebang(RopeSlice<'a>),
+    Match(Cow<'a, str>),
+    Filename(Cow<'a, str>),
+    Shebang(Cow<'a, str>),
 }
 
 #[derive(Clone, Debug)]
@@ -58,6 +69,9 @@ pub struct InjectionQueryMatch<'tree> {
     language: Language,
     scope: Option<InjectionScope>,
     node: Node<'tree>,
+    /// The range of `node` after applying the `#offset!` and `#trim!` directives. Always
+    /// contained in the range of `node`.
+    range: Range,
     last_match: bool,
     pattern: Pattern,
 }
@@ -102,6 +116,22 @@ impl InjectionsQuery {
         grammar: Grammar,
         injection_query_text: &str,
         local_query_text: &str,
+    ) -> Result<Self, query::ParseError> {
+        Self::with_predicates(
+            grammar,
+            injection_query_text,
+            local_query_text,
+            &PredicateRegistry::default(),
+        )
+    }
+
+    /// Create the query like [`InjectionsQuery::new`] with the custom predicates
+    /// in `registry` evaluated for every match.
+    pub fn with_predicates(
+        grammar: Grammar,
+        injection_query_text: &str,
+        local_query_text: &str,
+        registry: &PredicateRegistry,
     ) -> Result<Self, query::ParseError> {
         let mut query_source =
             String::with_capacity(injection_query_text.len() + local_query_text.len());
@@ -110,57 +140,66 @@ impl InjectionsQuery {
 
         let mut injection_properties: HashMap<Pattern, InjectionProperties> = HashMap::new();
         let mut not_scope_inherits = HashSet::new();
-        let injection_query = Query::new(grammar, injection_query_text, |pattern, predicate| {
-            match predicate {
-                // injections
-                UserPredicate::SetProperty {
-                    key: "injection.include-unnamed-children",
-                    val: None,
-                } => {
-                    injection_properties
-                        .entry(pattern)
-                        .or_default()
-                        .include_children = IncludedChildren::Unnamed
-                }
-                UserPredicate::SetProperty {
-                    key: "injection.include-children",
-                    val: None,
-                } => {
-                    injection_properties
-                        .entry(pattern)
-                        .or_default()
-                        .include_children = IncludedChildren::All
-                }
-                UserPredicate::SetProperty {
-                    key: "injection.language",
-                    val: Some(lang),
-                } => injection_properties.entry(pattern).or_default().language = Some(lang.into()),
-                UserPredicate::SetProperty {
-                    key: "injection.combined",
-                    val: None,
-                } => injection_properties.entry(pattern).or_default().combined = true,
-                predicate => {
-                    return Err(InvalidPredicateError::unknown(predicate));
-                }
-            }
-            Ok(())
-        })?;
-        let mut local_query = Query::new(grammar, local_query_text, |pattern, predicate| {
-            match predicate {
-                UserPredicate::SetProperty {
-                    key: "local.scope-inherits",
-                    val,
-                } => {
-                    if val.is_some_and(|val| val != "true") {
-                        not_scope_inherits.insert(pattern);
+        let injection_query = Query::with_predicates(
+            grammar,
+            injection_query_text,
+            registry,
+            |pattern, predicate| {
+                match predicate {
+                    // injections
+                    UserPredicate::SetProperty {
+                        key: "injection.include-unnamed-children",
+                        val: None,
+                    } => {
+                        injection_properties
+                            .entry(pattern)
+                            .or_default()
+                            .include_children = IncludedChildren::Unnamed
+                    }
+                    UserPredicate::SetProperty {
+                        key: "injection.include-children",
+                        val: None,
+                    } => {
+                        injection_properties
+                            .entry(pattern)
+                            .or_default()
+                            .include_children = IncludedChildren::All
+                    }
+                    UserPredicate::SetProperty {
+                        key: "injection.language",
+                        val: Some(lang),
+                    } => {
+                        injection_properties.entry(pattern).or_default().language =
+                            Some(lang.into())
+                    }
+                    UserPredicate::SetProperty {
+                        key: "injection.combined",
+                        val: None,
+                    } => injection_properties.entry(pattern).or_default().combined = true,
+                    predicate => {
+                        return Err(InvalidPredicateError::unknown(predicate));
                     }
                 }
-                predicate => {
-                    return Err(InvalidPredicateError::unknown(predicate));
+                Ok(())
+            },
+        )?;
+        let mut local_query =
+            Query::with_predicates(grammar, local_query_text, registry, |pattern, predicate| {
+                match predicate {
+                    UserPredicate::SetProperty {
+                        key: "local.scope-inherits",
+                        val,
+                    } => {
+                        if val.is_some_and(|val| val != "true") {
+                            not_scope_inherits.insert(pattern);
+                        }
+                    }
+                    predicate => {
+                        return Err(InvalidPredicateError::unknown(predicate));
+                    }
                 }
-            }
-            Ok(())
-        })?;
+                Ok(())
+            })?;
 
         // The injection queries do not track references - these are read by the highlight
         // query instead.
@@ -195,9 +234,9 @@ impl InjectionsQuery {
 
     fn process_match<'a, 'tree>(
         &self,
-        query_match: &QueryMatch<'a, 'tree>,
+        query_match: &QueryMatch<'_, 'tree>,
         node_idx: MatchedNodeIdx,
-        source: RopeSlice<'a>,
+        source: impl ChunkedText<'a>,
         loader: impl LanguageLoader,
     ) -> Option<InjectionQueryMatch<'tree>> {
         let properties = self
@@ -209,34 +248,41 @@ impl InjectionsQuery {
         let mut marker = None;
         let mut last_content_node = 0;
         let mut content_nodes = 0;
+        let pattern = query_match.pattern();
+        let captured_text = |matched_node: &tree_sitter::MatchedNode| {
+            let range = matched_node.node.byte_range();
+            let text = source.byte_text(range.start as usize..range.end as usize);
+            self.injection_query
+                .capture_text(pattern, matched_node.capture, text)
+        };
         for (i, matched_node) in query_match.matched_nodes().enumerate() {
             let capture = Some(matched_node.capture);
             if capture == self.injection_language_capture {
-                let range = matched_node.node.byte_range();
-                marker = Some(InjectionLanguageMarker::Match(
-                    source.byte_slice(range.start as usize..range.end as usize),
-                ));
+                marker = Some(InjectionLanguageMarker::Match(captured_text(matched_node)));
             } else if capture == self.injection_filename_capture {
-                let range = matched_node.node.byte_range();
-                marker = Some(InjectionLanguageMarker::Filename(
-                    source.byte_slice(range.start as usize..range.end as usize),
-                ));
+                marker = Some(InjectionLanguageMarker::Filename(captured_text(
+                    matched_node,
+                )));
             } else if capture == self.injection_shebang_capture {
                 let range = matched_node.node.byte_range();
-                let node_slice = source.byte_slice(range.start as usize..range.end as usize);
+                let node_text = source.byte_text(range.start as usize..range.end as usize);
 
                 // some languages allow space and newlines before the actual string content
                 // so a shebang could be on either the first or second line
-                let lines = if let Ok(end) = node_slice.try_line_to_byte(2) {
-                    node_slice.byte_slice(..end)
-                } else {
-                    node_slice
-                };
+                let lines_end = node_text
+                    .match_indices('\n')
+                    .nth(1)
+                    .map_or(node_text.len(), |(i, _)| i + 1);
+                let lines = &node_text[..lines_end];
 
                 marker = SHEBANG_REGEX
                     .captures_iter(regex_cursor::Input::new(lines))
                     .map(|cap| {
-                        let cap = lines.byte_slice(cap.get_group(1).unwrap().range());
+                        let range = cap.get_group(1).unwrap().range();
+                        let cap = match &node_text {
+                            Cow::Borrowed(text) => Cow::Borrowed(&text[range]),
+                            Cow::Owned(text) => Cow::Owned(text[range].to_owned()),
+                        };
                         InjectionLanguageMarker::Shebang(cap)
                     })
                     .next()
@@ -265,11 +311,24 @@ impl InjectionsQuery {
             None
         };
 
+        let matched_node = query_match.matched_node(node_idx);
+        let node_range = matched_node.node.byte_range();
+        let range = if self.injection_query.pattern_directives(pattern).is_empty() {
+            node_range.clone()
+        } else {
+            self.injection_query.capture_range(
+                pattern,
+                matched_node.capture,
+                node_range,
+                &mut ChunkedInput::new(source),
+            )
+        };
         Some(InjectionQueryMatch {
             language,
             scope,
             include_children: properties.include_children,
-            node: query_match.matched_node(node_idx).node.clone(),
+            node: matched_node.node.clone(),
+            range,
             last_match: last_content_node == node_idx,
             pattern: query_match.pattern(),
         })
@@ -289,16 +348,16 @@ impl InjectionsQuery {
     /// ```
     /// is possible and will always result in iteration order [range1, range2].
     /// This case should be handled by the calling function
-    fn execute<'a>(
+    fn execute<'a, 's: 'a>(
         &'a self,
         node: &Node<'a>,
-        source: RopeSlice<'a>,
+        source: impl ChunkedText<'s> + 'a,
         loader: &'a impl LanguageLoader,
-    ) -> impl Iterator<Item = InjectionQueryMatch<'a>> + 'a {
+    ) -> impl Iterator<Item = InjectionQueryMatch<'a>> + Captures<'s> + 'a {
         let mut cursor = InactiveQueryCursor::new();
-        cursor.set_byte_range(0..u32::MAX);
         cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
-        let mut cursor = cursor.execute_query(&self.injection_query, node, source);
+        let mut cursor =
+            cursor.execute_query(&self.injection_query, node, ChunkedInput::new(source));
         let injection_content_capture = self.injection_content_capture.unwrap();
         let iter = iter::from_fn(move || loop {
             let (query_match, node_idx) = cursor.next_matched_node()?;
@@ -309,11 +368,10 @@ impl InjectionsQuery {
                 query_match.remove();
                 continue;
             };
-            let range = query_match.matched_node(node_idx).node.byte_range();
             if mat.last_match {
                 query_match.remove();
             }
-            if range.is_empty() {
+            if mat.range.is_empty() {
                 continue;
             }
             break Some(mat);
@@ -364,11 +422,11 @@ impl InjectionsQuery {
 }
 
 impl Syntax {
-    pub(crate) fn run_injection_query(
+    pub(crate) fn run_injection_query<'a>(
         &mut self,
         layer: Layer,
         edits: &[tree_sitter::InputEdit],
-        source: RopeSlice<'_>,
+        source: impl ChunkedText<'a>,
         loader: &impl LanguageLoader,
         mut parse_layer: impl FnMut(Layer),
     ) {
@@ -389,7 +447,8 @@ impl Syntax {
         let parent_ranges = take(&mut layer_data.ranges);
         let parse_tree = layer_data.parse_tree.take().unwrap();
         let mut injections: Vec<Injection> = Vec::with_capacity(layer_data.injections.len());
-        let mut old_injections = take(&mut layer_data.injections).into_iter().peekable();
+        let all_old_injections = take(&mut layer_data.injections);
+        let mut old_injections = all_old_injections.iter().cloned().peekable();
 
         let injection_query = injections_query.execute(&parse_tree.root_node(), source, loader);
 
@@ -457,29 +516,51 @@ impl Syntax {
             }
 
             let old_len = injections.len();
-            intersect_ranges(mat.include_children, mat.node, &parent_ranges, |range| {
-                layer_data.ranges.push(tree_sitter::Range {
-                    start_point: tree_sitter::Point::ZERO,
-                    end_point: tree_sitter::Point::ZERO,
-                    start_byte: range.start,
-                    end_byte: range.end,
-                });
-                injections.push(Injection {
-                    range,
-                    layer,
-                    matched_node_range: matched_node_range.clone(),
-                });
-            });
+            intersect_ranges(
+                mat.include_children,
+                mat.node,
+                mat.range,
+                &parent_ranges,
+                |range| {
+                    layer_data.ranges.push(tree_sitter::Range {
+                        start_point: tree_sitter::Point::ZERO,
+                        end_point: tree_sitter::Point::ZERO,
+                        start_byte: range.start,
+                        end_byte: range.end,
+                    });
+                    injections.push(Injection {
+                        range,
+                        layer,
+                        matched_node_range: matched_node_range.clone(),
+                    });
+                },
+            );
             if old_len != insert_position {
                 let inserted = injections.len() - old_len;
                 injections[insert_position..].rotate_right(inserted)
             }
         }
 
+        // Injections whose layer was not matched again are removed when dead layers are
+        // pruned. The text they covered is now highlighted by this layer instead.
+        let removed_ranges: Vec<_> = all_old_injections
+            .into_iter()
+            .filter(|injection| {
+                !injection.range.is_empty() && !self.layer(injection.layer).flags.touched
+            })
+            .map(|injection| injection.range)
+            .collect();
+
         let layer_data = &mut self.layer_mut(layer);
         layer_data.ranges = parent_ranges;
         layer_data.parse_tree = Some(parse_tree);
         layer_data.injections = injections;
+        if !removed_ranges.is_empty() {
+            layer_data.changed_ranges.extend(removed_ranges);
+            layer_data
+                .changed_ranges
+                .sort_unstable_by_key(|range| range.start);
+        }
     }
 
     /// Maps the layers injection ranges through edits to enable incremental re-parsing.
@@ -603,6 +684,10 @@ impl Syntax {
                     flags: LayerUpdateFlags::default(),
                     parent: Some(parent),
                     locals: Locals::default(),
+                    changed_ranges: Vec::new(),
+                    stale: false,
+                    deferred: false,
+                    out_of_budget: false,
                 });
                 Layer(layer as u32)
             }
@@ -632,13 +717,15 @@ impl Syntax {
     }
 }
 
+/// Calls `push_range` for the parts of `range` (the range of `node` narrowed by directives)
+/// which are not covered by excluded children and which are inside the `parent_ranges`.
 fn intersect_ranges(
     include_children: IncludedChildren,
     node: Node,
+    range: Range,
     parent_ranges: &[tree_sitter::Range],
     push_range: impl FnMut(Range),
 ) {
-    let range = node.byte_range();
     let i = parent_ranges.partition_point(|parent_range| parent_range.end_byte <= range.start);
     let parent_ranges = parent_ranges[i..]
         .iter()
@@ -671,7 +758,10 @@ fn intersect_ranges_impl(
     mut push_range: impl FnMut(Range),
 ) {
     let mut start = range.start;
-    let mut excluded_ranges = excluded_ranges.filter(|range| !range.is_empty()).peekable();
+    let mut excluded_ranges = excluded_ranges
+        .map(|excluded| excluded.start.max(range.start)..excluded.end.min(range.end))
+        .filter(|range| !range.is_empty())
+        .peekable();
     let mut parent_ranges = parent_ranges.peekable();
     loop {
         let parent_range = parent_ranges.peek().unwrap().clone();
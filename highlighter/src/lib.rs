@@ -1,22 +1,26 @@
 use locals::Locals;
-use ropey::RopeSlice;
 
 use slab::Slab;
 
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::time::Duration;
-use tree_sitter::{IncompatibleGrammarError, Node, Tree};
+use tree_sitter::{ChunkedText, IncompatibleGrammarError, Node, SyntaxError, Tree};
 
+pub use crate::background::{BackgroundUpdate, SharedSyntax};
 pub use crate::config::{read_query, LanguageConfig, LanguageLoader};
 pub use crate::injections_query::{InjectionLanguageMarker, InjectionsQuery};
 use crate::parse::LayerUpdateFlags;
+#[cfg(feature = "parallel")]
+pub use crate::parse::ParsePool;
+pub use crate::parse::{MaybeSend, ParseLimit, UpdateReport};
 pub use crate::tree_cursor::TreeCursor;
 pub use tree_sitter;
 // pub use pretty_print::pretty_print_tree;
 // pub use tree_cursor::TreeCursor;
 
+mod background;
 mod config;
+pub mod edits;
 pub mod highlighter;
 mod injections_query;
 mod parse;
@@ -81,19 +85,41 @@ impl Language {
 /// separate injections. That is done while parsing/running the query capture. As
 /// a result the injections form a tree. Note that such other queries must account for
 /// such multi injection nodes.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Syntax {
     layers: Slab<LayerData>,
     root: Layer,
 }
 
 impl Syntax {
-    pub fn new(
-        source: RopeSlice,
+    pub fn new<'a>(
+        source: impl ChunkedText<'a> + MaybeSend,
         language: Language,
-        timeout: Duration,
+        limit: impl Into<ParseLimit>,
         loader: &impl LanguageLoader,
     ) -> Result<Self, Error> {
+        let mut syntax = Self::empty(language);
+        syntax
+            .update_impl(source, limit.into(), &[], None, loader)
+            .map(|_| syntax)
+    }
+
+    /// Like [`Syntax::new`] but only parses the injection layers which intersect the byte
+    /// range `priority_range`. See [`Syntax::update_prioritized`].
+    pub fn new_prioritized<'a>(
+        source: impl ChunkedText<'a> + MaybeSend,
+        language: Language,
+        limit: impl Into<ParseLimit>,
+        priority_range: Range,
+        loader: &impl LanguageLoader,
+    ) -> Result<Self, Error> {
+        let mut syntax = Self::empty(language);
+        syntax
+            .update_impl(source, limit.into(), &[], Some(priority_range), loader)
+            .map(|_| syntax)
+    }
+
+    fn empty(language: Language) -> Self {
         let root_layer = LayerData {
             parse_tree: None,
             language,
@@ -107,15 +133,17 @@ impl Syntax {
             injections: Vec::new(),
             parent: None,
             locals: Locals::default(),
+            changed_ranges: Vec::new(),
+            stale: false,
+            deferred: false,
+            out_of_budget: false,
         };
         let mut layers = Slab::with_capacity(32);
         let root = layers.insert(root_layer);
-        let mut syntax = Self {
+        Self {
             root: Layer(root as u32),
             layers,
-        };
-
-        syntax.update(source, timeout, &[], loader).map(|_| syntax)
+        }
     }
 
     pub fn layer(&self, layer: Layer) -> &LayerData {
@@ -190,9 +218,29 @@ impl Syntax {
         cursor
     }
 
-    pub fn walk(&self) -> TreeCursor {
+    pub fn walk(&self) -> TreeCursor<'_> {
         TreeCursor::new(self)
     }
+
+    /// Iterate over the layers whose syntax changed during the last [`Syntax::update`]
+    /// together with the changed byte ranges. See [`LayerData::changed_ranges`].
+    pub fn changed_ranges(&self) -> impl Iterator<Item = (Layer, &[Range])> {
+        self.layers
+            .iter()
+            .filter(|(_, layer)| !layer.changed_ranges.is_empty())
+            .map(|(idx, layer)| (Layer(idx as u32), &*layer.changed_ranges))
+    }
+
+    /// Iterate over the syntax errors of every layer.
+    ///
+    /// Errors are yielded per layer (in no particular layer order) and in document order
+    /// within each layer. See [`Tree::errors`].
+    pub fn errors(&self) -> impl Iterator<Item = (Layer, SyntaxError<'_>)> {
+        self.layers
+            .iter()
+            .filter_map(|(idx, layer)| Some((Layer(idx as u32), layer.tree()?)))
+            .flat_map(|(layer, tree)| tree.errors().map(move |error| (layer, error)))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -202,7 +250,7 @@ pub struct Injection {
     matched_node_range: Range,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LayerData {
     pub language: Language,
     parse_tree: Option<Tree>,
@@ -215,6 +263,14 @@ pub struct LayerData {
     flags: LayerUpdateFlags,
     parent: Option<Layer>,
     locals: Locals,
+    /// Sorted byte ranges whose syntax changed during the last update.
+    changed_ranges: Vec<Range>,
+    /// Set when parsing this layer timed out during the last update.
+    stale: bool,
+    /// Set when parsing this layer was deferred because it is outside of the priority range.
+    deferred: bool,
+    /// Set when [`Syntax::parse_deferred`] ran out of budget while parsing this layer.
+    out_of_budget: bool,
 }
 
 /// This PartialEq implementation only checks if that
@@ -248,6 +304,39 @@ impl LayerData {
         self.parse_tree.as_ref()
     }
 
+    /// Returns the byte ranges of this layer whose syntax changed during the last
+    /// [`Syntax::update`].
+    ///
+    /// For re-parsed layers these are the ranges reported by [`Tree::changed_ranges`],
+    /// plus the ranges of injections which were removed from this layer. Layers which
+    /// were parsed for the first time report all of their ranges. Note that tree-sitter
+    /// only reports structural changes: text which was edited without changing the
+    /// shape of the tree is not included.
+    pub fn changed_ranges(&self) -> &[Range] {
+        &self.changed_ranges
+    }
+
+    /// Returns `true` if parsing this layer timed out during the last [`Syntax::update`].
+    ///
+    /// The [`tree`](Self::tree) of a stale layer is the previous tree with the edits applied
+    /// (or `None` if the layer was never parsed successfully) so it may not match the source
+    /// text. Stale layers are reparsed by the next update.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Returns `true` if parsing this layer was deferred because it is outside of the
+    /// priority range of the last [`Syntax::update_prioritized`].
+    ///
+    /// Like for [stale](Self::is_stale) layers the tree may not match the source text. Deferred
+    /// layers are parsed by [`Syntax::parse_deferred`] or by a later update which includes
+    /// them in its priority range. The [`Highlighter`](crate::highlighter::Highlighter) and
+    /// [`QueryIter`](crate::query_iter::QueryIter) skip layers without a tree so a deferred
+    /// layer which was never parsed is not highlighted.
+    pub fn is_deferred(&self) -> bool {
+        self.deferred
+    }
+
     /// Returns the injection range **within this layers** that contains `idx`.
     /// This function will not descend into nested injections
     pub fn injection_at_byte_idx(&self, idx: u32) -> Option<&Injection> {
@@ -271,6 +360,7 @@ impl LayerData {
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     Timeout,
+    Cancelled,
     ExceededMaximumSize,
     InvalidRanges,
     Unknown,
@@ -282,6 +372,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Timeout => f.write_str("configured timeout was exceeded"),
+            Self::Cancelled => f.write_str("parsing was cancelled"),
             Self::ExceededMaximumSize => f.write_str("input text exceeds the maximum allowed size"),
             Self::InvalidRanges => f.write_str("invalid ranges"),
             Self::Unknown => f.write_str("an unknown error occurred"),
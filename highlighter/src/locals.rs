@@ -5,8 +5,7 @@ use std::{
 
 use hashbrown::HashMap;
 use kstring::KString;
-use ropey::RopeSlice;
-use tree_sitter::{Capture, InactiveQueryCursor};
+use tree_sitter::{Capture, ChunkedInput, ChunkedText, InactiveQueryCursor};
 
 use crate::{LanguageConfig, LanguageLoader, Layer, Range, Syntax, TREE_SITTER_MATCH_LIMIT};
 
@@ -20,7 +19,7 @@ impl Scope {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Locals {
     scopes: Vec<ScopeData>,
 }
@@ -149,13 +148,13 @@ impl ScopeCursor<'_> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Definition {
     pub capture: Capture,
     pub range: Range,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ScopeData {
     definitions: HashMap<KString, Definition>,
     range: Range,
@@ -169,10 +168,10 @@ pub struct ScopeData {
 }
 
 impl Syntax {
-    pub(crate) fn run_local_query(
+    pub(crate) fn run_local_query<'a>(
         &mut self,
         layer: Layer,
-        source: RopeSlice<'_>,
+        source: impl ChunkedText<'a>,
         loader: &impl LanguageLoader,
     ) {
         let layer_data = &mut self.layer_mut(layer);
@@ -190,9 +189,12 @@ impl Syntax {
 
         let root = layer_data.parse_tree.as_ref().unwrap().root_node();
         let mut cursor = InactiveQueryCursor::new();
-        cursor.set_byte_range(0..u32::MAX);
         cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
-        let mut cursor = cursor.execute_query(&injection_query.local_query, &root, source);
+        let mut cursor = cursor.execute_query(
+            &injection_query.local_query,
+            &root,
+            ChunkedInput::new(source),
+        );
         let mut locals = Locals::default();
         let mut scope = Scope::ROOT;
 
@@ -216,10 +218,7 @@ impl Syntax {
                     parent: Some(scope),
                 });
             } else if definition_captures.contains_key(&capture) {
-                let text = match source
-                    .byte_slice(range.start as usize..range.end as usize)
-                    .into()
-                {
+                let text = match source.byte_text(range.start as usize..range.end as usize) {
                     Cow::Borrowed(inner) => KString::from_ref(inner),
                     Cow::Owned(inner) => KString::from_string(inner),
                 };
@@ -1,20 +1,178 @@
+use std::collections::VecDeque;
 use std::mem::take;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use ropey::RopeSlice;
-use tree_sitter::{InactiveQueryCursor, Parser};
+use hashbrown::HashMap;
+use tree_sitter::{ChunkedInput, ChunkedText, Grammar, InactiveQueryCursor, Parser};
 
 use crate::config::LanguageLoader;
-use crate::{Error, LayerData, Syntax, TREE_SITTER_MATCH_LIMIT};
+use crate::{Error, Layer, LayerData, Range, Syntax, TREE_SITTER_MATCH_LIMIT};
+
+#[cfg(feature = "parallel")]
+mod pool;
+
+#[cfg(feature = "parallel")]
+pub use pool::ParsePool;
+
+/// A bound on the source text of [`Syntax::new`] and [`Syntax::update`]. With the `parallel`
+/// feature the text is shared with the threads of a [`ParsePool`] so it must be `Send`.
+#[cfg(feature = "parallel")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "parallel")]
+impl<T: Send> MaybeSend for T {}
+
+/// A bound on the source text of [`Syntax::new`] and [`Syntax::update`]. It is implemented
+/// for all types unless the `parallel` feature is enabled.
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSend for T {}
+
+/// Limits how long parsing may take (and which threads it may use) in [`Syntax::new`]
+/// and [`Syntax::update`].
+///
+/// A `Duration` converts into a limit with just a timeout so existing callers can keep
+/// passing a fixed timeout.
+#[derive(Debug, Clone, Default)]
+pub struct ParseLimit {
+    /// The maximum duration that parsing a single layer may take.
+    pub timeout: Option<Duration>,
+    /// A flag which cancels the parse as soon as it is set to `true`, for example because the
+    /// document was edited again while a background parse was running.
+    pub cancellation_flag: Option<Arc<AtomicBool>>,
+    /// A pool used to parse sibling injection layers (for example the code blocks of a
+    /// markdown document) concurrently. Without a pool all layers are parsed on the calling
+    /// thread.
+    ///
+    /// With a pool the injection layers are parsed breadth-first, one level of injections
+    /// after another, instead of depth-first. The resulting syntax trees are the same and do
+    /// not depend on the number of threads.
+    #[cfg(feature = "parallel")]
+    pub parse_pool: Option<Arc<ParsePool>>,
+}
+
+impl ParseLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_flag = Some(flag);
+        self
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn with_parse_pool(mut self, pool: Arc<ParsePool>) -> Self {
+        self.parse_pool = Some(pool);
+        self
+    }
+
+    fn parser(&self) -> Parser {
+        let mut parser = Parser::new();
+        self.configure(&mut parser);
+        parser
+    }
+
+    /// Applies the timeout and cancellation flag to a (possibly reused) parser.
+    fn configure(&self, parser: &mut Parser) {
+        match self.timeout {
+            Some(timeout) => parser.set_timeout(timeout),
+            None => parser.clear_timeout(),
+        }
+        parser.set_cancellation_flag(self.cancellation_flag.clone());
+    }
+
+    /// Whether sibling layers may be parsed concurrently.
+    fn is_parallel(&self) -> bool {
+        #[cfg(feature = "parallel")]
+        return self.parse_pool.is_some();
+        #[cfg(not(feature = "parallel"))]
+        false
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+}
+
+impl From<Duration> for ParseLimit {
+    fn from(timeout: Duration) -> Self {
+        Self::new().with_timeout(timeout)
+    }
+}
+
+/// The result of a successful [`Syntax::update`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateReport {
+    /// Injection layers whose parse exceeded the timeout, in no particular order.
+    ///
+    /// These layers keep their previous tree (with the edits applied) or no tree if they were
+    /// never parsed successfully. They are reparsed by the next update. See
+    /// [`LayerData::is_stale`].
+    pub stale_layers: Vec<Layer>,
+    /// Injection layers outside of the priority range of [`Syntax::update_prioritized`] which
+    /// were not parsed, in no particular order. See [`LayerData::is_deferred`].
+    pub deferred_layers: Vec<Layer>,
+}
+
+impl UpdateReport {
+    /// Returns `true` if all layers were parsed successfully.
+    pub fn is_complete(&self) -> bool {
+        self.stale_layers.is_empty() && self.deferred_layers.is_empty()
+    }
+}
 
 impl Syntax {
-    pub fn update(
+    /// Applies the `edits` to all layers and reparses them.
+    ///
+    /// If parsing an injection layer exceeds the timeout of the `limit` the update still
+    /// succeeds: the root layer and all other injections are updated as usual and the layer is
+    /// reported as stale in the returned [`UpdateReport`]. A timeout of the root layer or a
+    /// cancellation fails the update.
+    pub fn update<'a>(
         &mut self,
-        source: RopeSlice,
-        timeout: Duration,
+        source: impl ChunkedText<'a> + MaybeSend,
+        limit: impl Into<ParseLimit>,
         edits: &[tree_sitter::InputEdit],
         loader: &impl LanguageLoader,
-    ) -> Result<(), Error> {
+    ) -> Result<UpdateReport, Error> {
+        self.update_impl(source, limit.into(), edits, None, loader)
+    }
+
+    /// Like [`Syntax::update`] but only (re)parses the injection layers which intersect the
+    /// byte range `priority_range`, typically the visible part of the document.
+    ///
+    /// The other injection layers are deferred: they keep their edited trees until they are
+    /// parsed by [`Syntax::parse_deferred`] or by a later update which includes them. The
+    /// root layer is always parsed.
+    pub fn update_prioritized<'a>(
+        &mut self,
+        source: impl ChunkedText<'a> + MaybeSend,
+        limit: impl Into<ParseLimit>,
+        edits: &[tree_sitter::InputEdit],
+        priority_range: Range,
+        loader: &impl LanguageLoader,
+    ) -> Result<UpdateReport, Error> {
+        self.update_impl(source, limit.into(), edits, Some(priority_range), loader)
+    }
+
+    pub(crate) fn update_impl<'a>(
+        &mut self,
+        source: impl ChunkedText<'a> + MaybeSend,
+        limit: ParseLimit,
+        edits: &[tree_sitter::InputEdit],
+        priority_range: Option<Range>,
+        loader: &impl LanguageLoader,
+    ) -> Result<UpdateReport, Error> {
         // size limit of 512MiB, TS just cannot handle files this big (too
         // slow). Furthermore, TS uses 32 (signed) bit indices so this limit
         // must never be raised above 2GiB
@@ -22,6 +180,13 @@ impl Syntax {
             return Err(Error::ExceededMaximumSize);
         }
 
+        // With a parse pool layers are processed in batches: first all layers of a batch are
+        // (re)parsed concurrently because they don't depend on each other, then the injection
+        // queries of the batch's layers are run in order. The injected layers found by those
+        // queries form the next batch. This order is the same for any number of threads so
+        // the result is deterministic. Without a pool each batch is the last queued layer so
+        // the layers are processed depth-first.
+        let batched = limit.is_parallel();
         let mut queue = Vec::with_capacity(32);
         let root_flags = &mut self.layer_mut(self.root).flags;
         // The root layer is always considered.
@@ -30,45 +195,276 @@ impl Syntax {
         root_flags.modified = true;
         queue.push(self.root);
 
-        let mut parser = Parser::new();
-        parser.set_timeout(timeout);
+        let mut parser = limit.parser();
         let mut cursor = InactiveQueryCursor::new();
         // TODO: might need to set cursor range
-        cursor.set_byte_range(0..u32::MAX);
         cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
 
-        while let Some(layer) = queue.pop() {
+        let mut to_parse = Vec::with_capacity(32);
+        while !queue.is_empty() {
+            let batch_start = if batched { 0 } else { queue.len() - 1 };
+            let batch = queue.split_off(batch_start);
+            for &layer in &batch {
+                let layer_data = self.layer_mut(layer);
+                layer_data.changed_ranges.clear();
+                if layer_data.ranges.is_empty() {
+                    // Skip re-parsing and querying layers without any ranges.
+                    continue;
+                }
+                if let Some(tree) = &mut layer_data.parse_tree {
+                    if layer_data.flags.moved || layer_data.flags.modified {
+                        for edit in edits.iter().rev() {
+                            // Apply the edits in reverse.
+                            // If we applied them in order then edit 1 would disrupt the
+                            // positioning of edit 2.
+                            tree.edit(edit);
+                        }
+                    }
+                    if layer_data.flags.modified || layer_data.stale || layer_data.deferred {
+                        // Re-parse the tree.
+                        to_parse.push(layer);
+                    }
+                } else {
+                    // always parse if this layer has never been parsed before
+                    to_parse.push(layer);
+                }
+            }
+            if let Some(priority_range) = &priority_range {
+                to_parse.retain(|&layer| {
+                    !self.defer_outside_priority_range(layer, priority_range, loader)
+                });
+            }
+            self.parse_layers(&to_parse, &mut parser, source, &limit, loader)?;
+            to_parse.clear();
+            for layer in batch {
+                let layer_data = self.layer(layer);
+                // Layers which timed out before they were ever parsed have no tree to query.
+                if layer_data.ranges.is_empty() || layer_data.parse_tree.is_none() {
+                    continue;
+                }
+                self.run_injection_query(layer, edits, source, loader, |layer| queue.push(layer));
+                self.run_local_query(layer, source, loader);
+            }
+        }
+
+        if self.layer(self.root).parse_tree.is_none() {
+            return Err(Error::NoRootConfig);
+        }
+
+        self.prune_dead_layers();
+        Ok(self.report())
+    }
+
+    /// Parses the layers which were deferred by [`Syntax::update_prioritized`] because they
+    /// were outside of the priority range, in document order, until the `budget` is
+    /// exhausted. Injections found in these layers are parsed as well.
+    ///
+    /// Layers which could not be parsed within the budget stay deferred and are listed in the
+    /// returned report. Like an update this replaces the
+    /// [changed ranges](LayerData::changed_ranges) of all layers.
+    ///
+    /// A layer whose parse runs out of budget is parsed from scratch by the next call because
+    /// tree-sitter can't resume a parse which timed out once the parser is used for another
+    /// layer. So that such a layer doesn't block the layers after it, it is only retried after
+    /// all other deferred layers were parsed. A layer which takes longer to parse than the
+    /// budget of a single call is therefore only parsed by an update which includes it in its
+    /// priority range.
+    pub fn parse_deferred<'a>(
+        &mut self,
+        source: impl ChunkedText<'a>,
+        budget: Duration,
+        loader: &impl LanguageLoader,
+    ) -> Result<UpdateReport, Error> {
+        let deadline = Instant::now() + budget;
+        // Layers which are not visited below must survive the pruning at the end. The
+        // descendants of visited layers are only kept if they are injected again.
+        for (_, layer_data) in self.layers.iter_mut() {
+            layer_data.flags.touched = true;
+            layer_data.changed_ranges.clear();
+        }
+        // Layers injected while parsing the deferred layers are new so they have no children
+        // yet: the index stays valid for all layers whose children are looked up.
+        let children = self.children();
+        let mut queue = VecDeque::new();
+        self.find_deferred(self.root, &children, &mut queue);
+        queue.make_contiguous().sort_by_key(|&layer| {
+            let layer_data = self.layer(layer);
+            let start = layer_data.ranges.first().map(|range| range.start_byte);
+            (layer_data.out_of_budget, start)
+        });
+
+        let limit = ParseLimit::new();
+        let mut parser = Parser::new();
+        while let Some(layer) = queue.pop_front() {
             let layer_data = self.layer_mut(layer);
             if layer_data.ranges.is_empty() {
-                // Skip re-parsing and querying layers without any ranges.
                 continue;
             }
-            if let Some(tree) = &mut layer_data.parse_tree {
-                if layer_data.flags.moved || layer_data.flags.modified {
-                    for edit in edits.iter().rev() {
-                        // Apply the edits in reverse.
-                        // If we applied them in order then edit 1 would disrupt the positioning
-                        // of edit 2.
-                        tree.edit(edit);
+            if layer_data.needs_parse() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    queue.push_front(layer);
+                    break;
+                }
+                parser.set_timeout(remaining);
+                let grammar = loader
+                    .get_config(layer_data.language)
+                    .map(|config| config.grammar);
+                match layer_data.parse(&mut parser, grammar, source, &limit) {
+                    Err(Error::Timeout) => {
+                        layer_data.out_of_budget = true;
+                        queue.push_front(layer);
+                        break;
                     }
+                    res => res?,
                 }
-                if layer_data.flags.modified {
-                    // Re-parse the tree.
-                    layer_data.parse(&mut parser, source, loader)?;
+                if layer_data.parse_tree.is_none() {
+                    continue;
                 }
-            } else {
-                // always parse if this layer has never been parsed before
-                layer_data.parse(&mut parser, source, loader)?;
             }
-            self.run_injection_query(layer, edits, source, loader, |layer| queue.push(layer));
+            self.set_descendants_touched(layer, &children, false);
+            self.run_injection_query(layer, &[], source, loader, |layer| queue.push_back(layer));
             self.run_local_query(layer, source, loader);
         }
-
-        if self.layer(self.root).parse_tree.is_none() {
-            return Err(Error::NoRootConfig);
+        // The budget is exhausted: the remaining layers and their injections are kept as-is.
+        for layer in queue {
+            self.set_descendants_touched(layer, &children, true);
+            let layer_data = self.layer(layer);
+            if !layer_data.ranges.is_empty() && layer_data.needs_parse() {
+                self.defer(layer, loader);
+            }
         }
 
         self.prune_dead_layers();
+        Ok(self.report())
+    }
+
+    /// Defers parsing an injection layer which is outside of the `priority_range`. Returns
+    /// `false` if the layer must be parsed now.
+    fn defer_outside_priority_range(
+        &mut self,
+        layer: Layer,
+        priority_range: &Range,
+        loader: &impl LanguageLoader,
+    ) -> bool {
+        let intersects_priority = self.layer(layer).ranges.iter().any(|range| {
+            range.start_byte < priority_range.end && priority_range.start < range.end_byte
+        });
+        layer != self.root && !intersects_priority && self.defer(layer, loader)
+    }
+
+    /// Marks `layer` as deferred unless there is no grammar to parse it with.
+    fn defer(&mut self, layer: Layer, loader: &impl LanguageLoader) -> bool {
+        let deferred = loader.get_config(self.layer(layer).language).is_some();
+        if deferred {
+            let layer_data = self.layer_mut(layer);
+            layer_data.deferred = true;
+            layer_data.stale = false;
+            layer_data.invalidate();
+        }
+        deferred
+    }
+
+    /// Returns the injected layers of each layer.
+    fn children(&self) -> HashMap<Layer, Vec<Layer>> {
+        let mut children: HashMap<Layer, Vec<Layer>> = HashMap::new();
+        for (idx, layer_data) in self.layers.iter() {
+            if let Some(parent) = layer_data.parent {
+                children.entry(parent).or_default().push(Layer(idx as u32));
+            }
+        }
+        children
+    }
+
+    /// Collects the deferred layers below `layer` which don't have a deferred ancestor.
+    fn find_deferred(
+        &self,
+        layer: Layer,
+        children: &HashMap<Layer, Vec<Layer>>,
+        deferred: &mut VecDeque<Layer>,
+    ) {
+        if self.layer(layer).deferred {
+            deferred.push_back(layer);
+            return;
+        }
+        for &child in children.get(&layer).into_iter().flatten() {
+            self.find_deferred(child, children, deferred);
+        }
+    }
+
+    fn set_descendants_touched(
+        &mut self,
+        ancestor: Layer,
+        children: &HashMap<Layer, Vec<Layer>>,
+        touched: bool,
+    ) {
+        let mut stack = vec![ancestor];
+        while let Some(layer) = stack.pop() {
+            for &child in children.get(&layer).into_iter().flatten() {
+                self.layer_mut(child).flags.touched = touched;
+                stack.push(child);
+            }
+        }
+    }
+
+    pub(crate) fn report(&self) -> UpdateReport {
+        let layers = |filter: fn(&LayerData) -> bool| {
+            self.layers
+                .iter()
+                .filter(|(_, layer)| filter(layer))
+                .map(|(idx, _)| Layer(idx as u32))
+                .collect()
+        };
+        UpdateReport {
+            stale_layers: layers(|layer| layer.stale),
+            deferred_layers: layers(|layer| layer.deferred),
+        }
+    }
+
+    /// Parses the given layers, concurrently if there is more than one layer and the `limit`
+    /// has a [parse pool](ParseLimit::parse_pool). If parsing fails the error of the first
+    /// failed layer (in the order of `layers`) is returned.
+    fn parse_layers<'a>(
+        &mut self,
+        layers: &[Layer],
+        parser: &mut Parser,
+        source: impl ChunkedText<'a> + MaybeSend,
+        limit: &ParseLimit,
+        loader: &impl LanguageLoader,
+    ) -> Result<(), Error> {
+        // The grammars are looked up on this thread so that the loader doesn't need to be
+        // `Sync`.
+        let grammar = |layer: &LayerData| {
+            loader
+                .get_config(layer.language)
+                .map(|config| config.grammar)
+        };
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = limit.parse_pool.as_deref().filter(|_| layers.len() > 1) {
+            let results = self.parse_layers_in_pool(pool, layers, source, limit, grammar);
+            return layers
+                .iter()
+                .zip(results)
+                .try_for_each(|(&layer, res)| self.finish_parse(layer, res));
+        }
+        for &layer in layers {
+            let layer_data = self.layer_mut(layer);
+            let grammar = grammar(layer_data);
+            let res = layer_data.parse(parser, grammar, source, limit);
+            self.finish_parse(layer, res)?;
+        }
+        Ok(())
+    }
+
+    /// Turns a timeout of an injection layer into a stale layer so that the rest of the
+    /// document can still be highlighted.
+    fn finish_parse(&mut self, layer: Layer, res: Result<(), Error>) -> Result<(), Error> {
+        if res != Err(Error::Timeout) || layer == self.root {
+            return res;
+        }
+        let layer_data = self.layer_mut(layer);
+        layer_data.stale = true;
+        layer_data.invalidate();
         Ok(())
     }
 
@@ -80,27 +476,63 @@ impl Syntax {
 }
 
 impl LayerData {
-    fn parse(
+    fn parse<'a>(
         &mut self,
         parser: &mut Parser,
-        source: RopeSlice,
-        loader: &impl LanguageLoader,
+        grammar: Option<Grammar>,
+        source: impl ChunkedText<'a>,
+        limit: &ParseLimit,
     ) -> Result<(), Error> {
-        let Some(config) = loader.get_config(self.language) else {
+        let Some(grammar) = grammar else {
             return Ok(());
         };
-        if let Err(err) = parser.set_grammar(config.grammar) {
+        if let Err(err) = parser.set_grammar(grammar) {
             return Err(Error::IncompatibleGrammar(self.language, err));
         }
         parser
             .set_included_ranges(&self.ranges)
             .map_err(|_| Error::InvalidRanges)?;
         let tree = parser
-            .parse(source, self.parse_tree.as_ref())
-            .ok_or(Error::Timeout)?;
+            .parse(ChunkedInput::new(source), self.parse_tree.as_ref())
+            .ok_or_else(|| {
+                if limit.is_cancelled() {
+                    Error::Cancelled
+                } else {
+                    Error::Timeout
+                }
+            })?;
+        self.changed_ranges = match &self.parse_tree {
+            Some(old_tree) => old_tree
+                .changed_ranges(&tree)
+                .into_iter()
+                .map(|range| range.start_byte..range.end_byte)
+                .collect(),
+            None => self
+                .ranges
+                .iter()
+                .map(|range| range.start_byte..range.end_byte)
+                .collect(),
+        };
         self.parse_tree = Some(tree);
+        self.stale = false;
+        self.deferred = false;
+        self.out_of_budget = false;
         Ok(())
     }
+
+    fn needs_parse(&self) -> bool {
+        self.parse_tree.is_none() || self.flags.modified || self.stale || self.deferred
+    }
+
+    /// Reports the whole layer as changed. Used for layers which were not reparsed: their
+    /// previous tree was edited so any part of the layer may have changed.
+    fn invalidate(&mut self) {
+        self.changed_ranges = self
+            .ranges
+            .iter()
+            .map(|range| range.start_byte..range.end_byte)
+            .collect();
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
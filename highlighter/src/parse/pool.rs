@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use hashbrown::HashMap;
+use tree_sitter::{ChunkedText, Grammar, Parser};
+
+use crate::parse::ParseLimit;
+use crate::{Error, Layer, LayerData, Syntax};
+
+/// A pool of threads which parse sibling injection layers (for example the code blocks of a
+/// markdown document) concurrently. Pass it to [`ParseLimit::with_parse_pool`] to opt into
+/// parsing on multiple threads.
+///
+/// The threads and their parsers live as long as the pool so a pool should be created once
+/// and shared between all documents, for example in an `Arc`. Each thread owns a single
+/// parser which is reused for every layer parsed on that thread.
+pub struct ParsePool {
+    threads: rayon::ThreadPool,
+    /// The parser of each thread, indexed by the thread's index in the pool. A thread only
+    /// ever locks its own parser so the locks are never contended.
+    parsers: Box<[Mutex<Parser>]>,
+}
+
+impl fmt::Debug for ParsePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParsePool")
+            .field("threads", &self.parsers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ParsePool {
+    /// Spawns a pool with the given number of threads.
+    pub fn new(threads: NonZeroUsize) -> io::Result<Self> {
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .thread_name(|i| format!("tree-house-parse-pool-{i}"))
+            .build()
+            .map_err(io::Error::other)?;
+        let parsers = (0..threads.current_num_threads())
+            .map(|_| Mutex::new(Parser::new()))
+            .collect();
+        Ok(Self { threads, parsers })
+    }
+
+    /// Returns the parser of the pool thread this is called on, configured for `limit`.
+    fn parser(&self, limit: &ParseLimit) -> MutexGuard<'_, Parser> {
+        let thread = rayon::current_thread_index().expect("not called on a thread of the pool");
+        let mut parser = self.parsers[thread]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        limit.configure(&mut parser);
+        parser
+    }
+}
+
+impl Syntax {
+    /// Parses the given layers on the threads of the `pool`. The results are returned in the
+    /// order of `layers`.
+    pub(super) fn parse_layers_in_pool<'a>(
+        &mut self,
+        pool: &ParsePool,
+        layers: &[Layer],
+        source: impl ChunkedText<'a> + Send,
+        limit: &ParseLimit,
+        grammar: impl Fn(&LayerData) -> Option<Grammar>,
+    ) -> Vec<Result<(), Error>> {
+        let mut layer_data: HashMap<u32, &mut LayerData> = self
+            .layers
+            .iter_mut()
+            .map(|(idx, layer_data)| (idx as u32, layer_data))
+            .collect();
+        let mut jobs: Vec<_> = layers
+            .iter()
+            .map(|layer| {
+                let layer_data = layer_data.remove(&layer.0).unwrap();
+                let grammar = grammar(layer_data);
+                (layer_data, grammar, Ok(()))
+            })
+            .collect();
+        let pending = &mut jobs;
+        pool.threads.scope(move |scope| {
+            for (layer_data, grammar, result) in pending {
+                scope.spawn(move |_| {
+                    let mut parser = pool.parser(limit);
+                    *result = layer_data.parse(&mut parser, *grammar, source, limit);
+                });
+            }
+        });
+        jobs.into_iter().map(|(_, _, result)| result).collect()
+    }
+}
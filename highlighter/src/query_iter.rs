@@ -1,72 +1,154 @@
 use core::slice;
+use std::collections::VecDeque;
+use std::fmt;
 use std::iter::Peekable;
 use std::mem::replace;
 use std::ops::RangeBounds;
+use std::time::{Duration, Instant};
 
 use hashbrown::HashMap;
-use ropey::RopeSlice;
 
 use crate::{
     locals::{Scope, ScopeCursor},
     Injection, Language, Layer, Range, Syntax, TREE_SITTER_MATCH_LIMIT,
 };
 use tree_sitter::{
-    Capture, InactiveQueryCursor, Node, Pattern, Query, QueryCursor, QueryMatch, RopeInput,
+    Capture, ChunkedInput, ChunkedText, InactiveQueryCursor, Node, Pattern, Point,
+    ProgressCallback, Query, QueryCursor, QueryMatch,
 };
 
+/// Additional limits for the query cursors of a [`QueryIter`], see
+/// [`QueryIter::with_options`].
+#[derive(Clone, Default)]
+pub struct QueryIterOptions {
+    /// Only return matches intersecting this range of (row, column) positions, in
+    /// addition to the byte range passed to the iterator.
+    pub point_range: Option<std::ops::Range<Point>>,
+    /// Only return matches whose captured nodes are fully contained in this byte range.
+    pub containing_range: Option<Range>,
+    /// The maximum depth at which patterns may start, relative to the root of each
+    /// injection layer. A depth of zero only matches the root node of each layer.
+    pub max_start_depth: Option<u32>,
+    /// The maximum duration of the whole iteration (across all injection layers). Once it
+    /// has expired no further matches are returned.
+    pub timeout: Option<Duration>,
+    /// Called periodically with the current byte offset (within the layer being queried)
+    /// while a query executes. The query of the layer is cancelled when it returns `true`.
+    pub progress_callback: Option<ProgressCallback>,
+}
+
+impl fmt::Debug for QueryIterOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryIterOptions")
+            .field("point_range", &self.point_range)
+            .field("containing_range", &self.containing_range)
+            .field("max_start_depth", &self.max_start_depth)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MatchedNode<'tree> {
     pub match_id: u32,
     pub pattern: Pattern,
     pub node: Node<'tree>,
+    /// The range of `node` after applying the `#offset!` and `#trim!` directives of the
+    /// pattern. Directives can only shrink the range: it is always contained in the range
+    /// of `node`.
+    pub range: Range,
     pub capture: Capture,
     pub scope: Scope,
 }
 
-struct LayerQueryIter<'a, 'tree> {
-    cursor: Option<QueryCursor<'a, 'tree, RopeInput<'a>>>,
+struct LayerQueryIter<'a, 'tree, Text: ChunkedText<'a>> {
+    query: Option<&'a Query>,
+    cursor: Option<QueryCursor<'a, 'tree, ChunkedInput<'a, Text>>>,
     peeked: Option<MatchedNode<'tree>>,
+    /// Matches which were read from the cursor ahead of time because directives moved the
+    /// start of an earlier match past them. Sorted by the start of their range.
+    pending: VecDeque<MatchedNode<'tree>>,
     language: Language,
     scope_cursor: ScopeCursor<'tree>,
 }
 
-impl<'a, 'tree> LayerQueryIter<'a, 'tree> {
+impl<'a, 'tree, Text: ChunkedText<'a>> LayerQueryIter<'a, 'tree, Text> {
     fn peek<Loader: QueryLoader<'a>>(
         &mut self,
-        source: RopeSlice<'_>,
+        source: Text,
         loader: &Loader,
     ) -> Option<&MatchedNode<'tree>> {
         if self.peeked.is_none() {
-            loop {
-                let (query_match, node_idx) = self.cursor.as_mut()?.next_matched_node()?;
-                let node = query_match.matched_node(node_idx);
-                let match_id = query_match.id();
-                let pattern = query_match.pattern();
-                let range = node.node.byte_range();
-                let scope = self.scope_cursor.advance(range.start);
-
-                if !loader.are_predicates_satisfied(
-                    self.language,
-                    &query_match,
-                    source,
-                    &self.scope_cursor,
-                ) {
-                    query_match.remove();
-                    continue;
-                }
+            self.peeked = self.next_in_order(source, loader);
+        }
+        self.peeked.as_ref()
+    }
 
-                self.peeked = Some(MatchedNode {
-                    match_id,
-                    pattern,
-                    // NOTE: `Node` is cheap to clone, it's essentially Copy.
-                    node: node.node.clone(),
-                    capture: node.capture,
-                    scope,
-                });
-                break;
+    /// Returns the next match ordered by the start of its (directive adjusted) range.
+    fn next_in_order<Loader: QueryLoader<'a>>(
+        &mut self,
+        source: Text,
+        loader: &Loader,
+    ) -> Option<MatchedNode<'tree>> {
+        loop {
+            let Some(matched_node) = self.next_from_cursor(source, loader) else {
+                return self.pending.pop_front();
+            };
+            // The cursor yields nodes in order of their start and directives only move the
+            // start of a range forward. Pending matches which start before this node can't
+            // be preceded by any later match.
+            let node_start = matched_node.node.start_byte();
+            let i = self
+                .pending
+                .partition_point(|pending| pending.range.start <= matched_node.range.start);
+            self.pending.insert(i, matched_node);
+            if self.pending[0].range.start <= node_start {
+                return self.pending.pop_front();
             }
         }
-        self.peeked.as_ref()
+    }
+
+    fn next_from_cursor<Loader: QueryLoader<'a>>(
+        &mut self,
+        source: Text,
+        loader: &Loader,
+    ) -> Option<MatchedNode<'tree>> {
+        loop {
+            let (query_match, node_idx) = self.cursor.as_mut()?.next_matched_node()?;
+            let node = query_match.matched_node(node_idx);
+            let match_id = query_match.id();
+            let pattern = query_match.pattern();
+            let range = node.node.byte_range();
+            let scope = self.scope_cursor.advance(range.start);
+
+            if !loader.are_predicates_satisfied(
+                self.language,
+                &query_match,
+                source,
+                &self.scope_cursor,
+            ) {
+                query_match.remove();
+                continue;
+            }
+
+            let query = self.query.expect("a cursor always has a query");
+            let capture = node.capture;
+            let matched_range = if query.pattern_directives(pattern).is_empty() {
+                range
+            } else {
+                query.capture_range(pattern, capture, range, &mut ChunkedInput::new(source))
+            };
+
+            break Some(MatchedNode {
+                match_id,
+                pattern,
+                // NOTE: `Node` is cheap to clone, it's essentially Copy.
+                node: node.node.clone(),
+                range: matched_range,
+                capture,
+                scope,
+            });
+        }
     }
 
     fn consume(&mut self) -> MatchedNode<'tree> {
@@ -74,29 +156,32 @@ impl<'a, 'tree> LayerQueryIter<'a, 'tree> {
     }
 }
 
-struct ActiveLayer<'a, 'tree, S> {
+struct ActiveLayer<'a, 'tree, Text: ChunkedText<'a>, S> {
     state: S,
-    query_iter: LayerQueryIter<'a, 'tree>,
+    query_iter: LayerQueryIter<'a, 'tree, Text>,
     injections: Peekable<slice::Iter<'a, Injection>>,
 }
 
 // data only needed when entering and exiting injections
 // separate struck to keep the QueryIter reasonably small
-struct QueryIterLayerManager<'a, 'tree, Loader, S> {
+struct QueryIterLayerManager<'a, 'tree, Text: ChunkedText<'a>, Loader, S> {
     range: Range,
+    options: QueryIterOptions,
+    deadline: Option<Instant>,
     loader: Loader,
-    src: RopeSlice<'a>,
+    src: Text,
     syntax: &'tree Syntax,
-    active_layers: HashMap<Layer, Box<ActiveLayer<'a, 'tree, S>>>,
+    active_layers: HashMap<Layer, Box<ActiveLayer<'a, 'tree, Text, S>>>,
     active_injections: Vec<Injection>,
 }
 
-impl<'a, 'tree: 'a, Loader, S> QueryIterLayerManager<'a, 'tree, Loader, S>
+impl<'a, 'tree: 'a, Text, Loader, S> QueryIterLayerManager<'a, 'tree, Text, Loader, S>
 where
+    Text: ChunkedText<'a>,
     Loader: QueryLoader<'a>,
     S: Default,
 {
-    fn init_layer(&mut self, injection: Injection) -> Box<ActiveLayer<'a, 'tree, S>> {
+    fn init_layer(&mut self, injection: Injection) -> Box<ActiveLayer<'a, 'tree, Text, S>> {
         self.active_layers
             .remove(&injection.layer)
             .unwrap_or_else(|| {
@@ -105,22 +190,34 @@ where
                 let injection_start = layer
                     .injections
                     .partition_point(|child| child.range.end < start_point);
-                let cursor = self
-                    .loader
-                    .get_query(layer.language)
+                let query = self.loader.get_query(layer.language);
+                let cursor = query
                     .and_then(|query| Some((query, layer.tree()?.root_node())))
                     .map(|(query, node)| {
                         let mut cursor = InactiveQueryCursor::new();
                         cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
-                        cursor.set_byte_range(self.range.clone());
-                        cursor.execute_query(query, &node, RopeInput::new(self.src))
+                        // `QueryIter::with_options` checked that the ranges are not reversed.
+                        cursor.set_byte_range(self.range.clone()).unwrap();
+                        if let Some(point_range) = &self.options.point_range {
+                            cursor.set_point_range(point_range.clone()).unwrap();
+                        }
+                        cursor.set_containing_byte_range(self.options.containing_range.clone());
+                        cursor.set_max_start_depth(self.options.max_start_depth);
+                        cursor
+                            .set_timeout(self.deadline.map(|deadline| {
+                                deadline.saturating_duration_since(Instant::now())
+                            }));
+                        cursor.set_progress_callback(self.options.progress_callback.clone());
+                        cursor.execute_query(query, &node, ChunkedInput::new(self.src))
                     });
                 Box::new(ActiveLayer {
                     state: S::default(),
                     query_iter: LayerQueryIter {
                         language: layer.language,
+                        query,
                         cursor,
                         peeked: None,
+                        pending: VecDeque::new(),
                         scope_cursor: layer.locals.scope_cursor(self.range.start),
                     },
                     injections: layer.injections[injection_start..].iter().peekable(),
@@ -129,22 +226,43 @@ where
     }
 }
 
-pub struct QueryIter<'a, 'tree, Loader: QueryLoader<'a>, LayerState = ()> {
-    layer_manager: Box<QueryIterLayerManager<'a, 'tree, Loader, LayerState>>,
-    current_layer: Box<ActiveLayer<'a, 'tree, LayerState>>,
+/// Iterates over the matches of per-language queries across all injection layers of a
+/// [`Syntax`] in document order.
+///
+/// The source text can be any [`ChunkedText`], for example a `ropey::RopeSlice` or a `&str`.
+pub struct QueryIter<'a, 'tree, Text: ChunkedText<'a>, Loader: QueryLoader<'a>, LayerState = ()> {
+    layer_manager: Box<QueryIterLayerManager<'a, 'tree, Text, Loader, LayerState>>,
+    current_layer: Box<ActiveLayer<'a, 'tree, Text, LayerState>>,
     current_injection: Injection,
 }
 
-impl<'a, 'tree: 'a, Loader, LayerState> QueryIter<'a, 'tree, Loader, LayerState>
+impl<'a, 'tree: 'a, Text, Loader, LayerState> QueryIter<'a, 'tree, Text, Loader, LayerState>
 where
+    Text: ChunkedText<'a>,
     Loader: QueryLoader<'a>,
     LayerState: Default,
 {
     pub fn new(
         syntax: &'tree Syntax,
-        src: RopeSlice<'a>,
+        src: Text,
+        loader: Loader,
+        range: impl RangeBounds<u32>,
+    ) -> Self {
+        Self::with_options(syntax, src, loader, range, QueryIterOptions::default())
+    }
+
+    /// Like [`QueryIter::new`] but the query cursors are additionally limited by the
+    /// given `options`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of `range` or of `options.point_range` is after its end.
+    pub fn with_options(
+        syntax: &'tree Syntax,
+        src: Text,
         loader: Loader,
         range: impl RangeBounds<u32>,
+        options: QueryIterOptions,
     ) -> Self {
         let start = match range.start_bound() {
             std::ops::Bound::Included(&i) => i,
@@ -157,6 +275,13 @@ where
             std::ops::Bound::Unbounded => src.len_bytes() as u32,
         };
         let range = start..end;
+        assert!(start <= end, "invalid range {range:?}");
+        if let Some(point_range) = &options.point_range {
+            assert!(
+                point_range.start <= point_range.end,
+                "invalid point range {point_range:?}"
+            );
+        }
         let node = syntax.tree().root_node();
         // create fake injection for query root
         let injection = Injection {
@@ -166,6 +291,8 @@ where
         };
         let mut layer_manager = Box::new(QueryIterLayerManager {
             range,
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            options,
             loader,
             src,
             syntax,
@@ -181,7 +308,7 @@ where
     }
 
     #[inline]
-    pub fn source(&self) -> RopeSlice<'a> {
+    pub fn source(&self) -> Text {
         self.layer_manager.src
     }
 
@@ -262,8 +389,9 @@ where
     }
 }
 
-impl<'a, 'tree: 'a, Loader, S> Iterator for QueryIter<'a, 'tree, Loader, S>
+impl<'a, 'tree: 'a, Text, Loader, S> Iterator for QueryIter<'a, 'tree, Text, Loader, S>
 where
+    Text: ChunkedText<'a>,
     Loader: QueryLoader<'a>,
     S: Default,
 {
@@ -281,7 +409,7 @@ where
                 .query_iter
                 .peek(self.layer_manager.src, &self.layer_manager.loader)
                 .filter(|matched_node| {
-                    matched_node.node.start_byte() <= self.current_injection.range.end
+                    matched_node.range.start <= self.current_injection.range.end
                 });
 
             match (next_match, next_injection) {
@@ -290,7 +418,7 @@ where
                         QueryIterEvent::ExitInjection { injection, state }
                     });
                 }
-                (Some(mat), _) if mat.node.byte_range().is_empty() => {
+                (Some(mat), _) if mat.range.is_empty() => {
                     self.current_layer.query_iter.consume();
                     continue;
                 }
@@ -300,13 +428,13 @@ where
                     return Some(QueryIterEvent::Match(matched_node));
                 }
                 (Some(matched_node), Some(injection))
-                    if matched_node.node.start_byte() < injection.range.end =>
+                    if matched_node.range.start < injection.range.end =>
                 {
                     // consume match
                     let matched_node = self.current_layer.query_iter.consume();
                     // ignore nodes that are overlapped by the injection
-                    if matched_node.node.start_byte() <= injection.range.start
-                        || injection.range.end < matched_node.node.end_byte()
+                    if matched_node.range.start <= injection.range.start
+                        || injection.range.end < matched_node.range.end
                     {
                         return Some(QueryIterEvent::Match(matched_node));
                     }
@@ -336,7 +464,7 @@ impl<S> QueryIterEvent<'_, S> {
     pub fn start_byte(&self) -> u32 {
         match self {
             QueryIterEvent::EnterInjection(injection) => injection.range.start,
-            QueryIterEvent::Match(mat) => mat.node.start_byte(),
+            QueryIterEvent::Match(mat) => mat.range.start,
             QueryIterEvent::ExitInjection { injection, .. } => injection.range.end,
         }
     }
@@ -345,11 +473,11 @@ impl<S> QueryIterEvent<'_, S> {
 pub trait QueryLoader<'a> {
     fn get_query(&mut self, lang: Language) -> Option<&'a Query>;
 
-    fn are_predicates_satisfied(
+    fn are_predicates_satisfied<'s>(
         &self,
         _lang: Language,
         _match: &QueryMatch<'_, '_>,
-        _source: RopeSlice<'_>,
+        _source: impl ChunkedText<'s>,
         _locals_cursor: &ScopeCursor<'_>,
     ) -> bool {
         true
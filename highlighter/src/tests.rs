@@ -1,19 +1,31 @@
-use std::borrow::Cow;
 use std::cell::RefCell;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use indexmap::{IndexMap, IndexSet};
 use once_cell::sync::Lazy;
 use once_cell::unsync::OnceCell;
+use proptest::test_runner::{Config as ProptestConfig, TestRunner};
+use ropey::Rope;
 use skidder::Repo;
-use tree_sitter::Grammar;
+use tree_sitter::{
+    query::InvalidPredicateError, CaptureQuantifier, ChunkedInput, ChunkedText, FieldId, Grammar,
+    InactiveQueryCursor, InputEdit, InvalidRangeError, LogType, OwnedNode, Parser, Pattern, Point,
+    PredicateRegistry, Query, SyntaxErrorKind, Tree, TreeCursor,
+};
 
+use crate::background;
 use crate::config::{LanguageConfig, LanguageLoader};
+use crate::edits;
 use crate::fixtures::{check_highlighter_fixture, check_injection_fixture};
-use crate::highlighter::Highlight;
+use crate::highlighter::{Highlight, HighlightEvent, Highlighter};
 use crate::injections_query::InjectionLanguageMarker;
-use crate::Language;
+use crate::query_iter::{QueryIter, QueryIterEvent, QueryIterOptions, QueryLoader};
+use crate::text_object::{CapturedNode, TextObjectQuery};
+use crate::{Error, Language, Layer, ParseLimit, SharedSyntax, Syntax, UpdateReport};
 
 static GRAMMARS: Lazy<Vec<PathBuf>> = Lazy::new(|| {
     let skidder_config = skidder_config();
@@ -113,16 +125,12 @@ impl TestLanguageLoader {
         self.languages[name]
     }
 
-    // TODO: remove on first use.
-    #[allow(dead_code)]
     fn overwrite_injections(&mut self, lang: &str, content: String) {
         let lang = self.get(lang);
         self.overwrites[lang.idx()].injections = Some(content);
         self.lang_config[lang.idx()] = OnceCell::new();
     }
 
-    // TODO: remove on first use.
-    #[allow(dead_code)]
     fn overwrite_highlights(&mut self, lang: &str, content: String) {
         let lang = self.get(lang);
         self.overwrites[lang.idx()].highlights = Some(content);
@@ -161,10 +169,7 @@ impl LanguageLoader for TestLanguageLoader {
     fn language_for_marker(&self, marker: InjectionLanguageMarker) -> Option<Language> {
         match marker {
             InjectionLanguageMarker::Name(name) => self.languages.get(name).copied(),
-            InjectionLanguageMarker::Match(text) => {
-                let name: Cow<str> = text.into();
-                self.languages.get(name.as_ref()).copied()
-            }
+            InjectionLanguageMarker::Match(name) => self.languages.get(name.as_ref()).copied(),
             _ => unimplemented!(),
         }
     }
@@ -224,6 +229,38 @@ fn injection_fixture(loader: &TestLanguageLoader, fixture: impl AsRef<Path>) {
     )
 }
 
+/// Returns the offset and the active highlights of each event of highlighting `src`.
+fn highlights<'a>(
+    syntax: &'a Syntax,
+    src: impl ChunkedText<'a>,
+    loader: &'a TestLanguageLoader,
+) -> Vec<(u32, Vec<Highlight>)> {
+    let mut highlighter = Highlighter::new(syntax, src, loader, ..);
+    let mut events = Vec::new();
+    while highlighter.next_event_offset() != u32::MAX {
+        let offset = highlighter.next_event_offset();
+        let (_, highlights) = highlighter.advance();
+        events.push((offset, highlights.collect()));
+    }
+    events
+}
+
+/// Calls `f` with a cursor on each node of `tree` in pre-order.
+fn walk_tree<'tree>(tree: &'tree Tree, mut f: impl FnMut(&TreeCursor<'tree>)) {
+    let mut cursor = tree.walk();
+    'traversal: loop {
+        f(&cursor);
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'traversal;
+            }
+        }
+    }
+}
+
 #[test]
 fn highlight() {
     let loader = TestLanguageLoader::new();
@@ -356,3 +393,1958 @@ fn injection_precedence() {
     highlight_fixture(&loader, "highlighter/rust_no_doc_comment.rs");
     injection_fixture(&loader, "injections/rust_no_doc_comment.rs");
 }
+
+#[test]
+fn node_field_access() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str("fn add(a: u32, b: u32) -> u32 { a + b }");
+    let syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let function = syntax.tree().root_node().child(0).unwrap();
+    assert_eq!(function.kind(), "function_item");
+
+    let name = function.child_by_field_name("name").unwrap();
+    assert_eq!(name.byte_range(), 3..6);
+    assert!(function.child_by_field_name("not-a-field").is_none());
+
+    let body_id = grammar.field_id_for_name("body").unwrap();
+    assert_eq!(grammar.field_name_for_id(body_id), Some("body"));
+    assert!(grammar.field_id_for_name("not-a-field").is_none());
+    let body = function.child_by_field_id(body_id).unwrap();
+    assert_eq!(body.kind(), "block");
+    assert_eq!(function.children_by_field_id(body_id).count(), 1);
+
+    let fields: Vec<_> = (0..function.child_count())
+        .map(|i| function.field_name_for_child(i))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            None,
+            Some("name"),
+            Some("parameters"),
+            None,
+            Some("return_type"),
+            Some("body")
+        ]
+    );
+    assert_eq!(function.field_name_for_named_child(0), Some("name"));
+
+    let mut cursor = function.walk();
+    assert!(cursor.goto_first_child());
+    assert!(cursor.goto_next_sibling());
+    assert_eq!(cursor.field_name(), Some("name"));
+    assert_eq!(cursor.field_id(), grammar.field_id_for_name("name"));
+
+    let expression = body.named_child(0).unwrap();
+    assert_eq!(expression.kind(), "binary_expression");
+    let operands: Vec<_> = expression
+        .children_by_field_name("left")
+        .chain(expression.children_by_field_name("right"))
+        .map(|node| node.byte_range())
+        .collect();
+    assert_eq!(operands, [32..33, 36..37]);
+}
+
+#[test]
+fn node_point_positions() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str("fn main() {\n    let x = 1;\n}\n");
+    let syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let root = syntax.tree().root_node();
+    let function = root.child(0).unwrap();
+    assert_eq!(function.start_point(), Point { row: 0, col: 0 });
+    assert_eq!(function.end_point(), Point { row: 2, col: 1 });
+    assert_eq!(function.range().end_byte, function.end_byte());
+
+    let ident = root
+        .named_descendant_for_point_range(Point { row: 1, col: 8 }, Point { row: 1, col: 9 })
+        .unwrap();
+    assert_eq!(ident.kind(), "identifier");
+    assert_eq!(ident.byte_range(), 20..21);
+    let node = root
+        .descendant_for_point_range(Point { row: 1, col: 13 }, Point { row: 1, col: 14 })
+        .unwrap();
+    assert_eq!(node.kind(), ";");
+
+    let mut cursor = function.walk();
+    assert_eq!(
+        cursor.goto_first_child_for_point(Point { row: 1, col: 0 }),
+        Some(3)
+    );
+    assert_eq!(cursor.node().kind(), "block");
+}
+
+#[test]
+fn syntax_errors() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str("fn main() {\n    let x = ;\n    foo(1\n}\n");
+    let syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let errors: Vec<_> = syntax.tree().errors().collect();
+    assert_eq!(errors.len(), 2);
+
+    assert_eq!(errors[0].kind, SyntaxErrorKind::Error);
+    assert_eq!(errors[0].node.byte_range(), 22..23);
+    assert_eq!(
+        errors[0].enclosing_node().unwrap().kind(),
+        "let_declaration"
+    );
+    assert!(errors[0].expected().contains(&";"));
+
+    assert_eq!(errors[1].kind, SyntaxErrorKind::Missing);
+    assert_eq!(errors[1].range().start_point, Point { row: 2, col: 9 });
+    assert_eq!(errors[1].range().start_byte, errors[1].range().end_byte);
+    assert_eq!(errors[1].enclosing_node().unwrap().kind(), "arguments");
+    assert_eq!(errors[1].expected(), [")"]);
+
+    assert_eq!(syntax.errors().count(), 2);
+    assert!(syntax.tree().root_node().has_error());
+    assert!(!errors[0].enclosing_node().unwrap().is_error());
+}
+
+#[test]
+fn changed_ranges() {
+    let loader = TestLanguageLoader::new();
+    let mut source = Rope::from_str("fn main() {}\n// hello\n");
+    let mut syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    // Every layer is new so all of them are reported as changed.
+    assert_eq!(syntax.changed_ranges().count(), 2);
+
+    source.insert(11, "let x = 1; ");
+    let edit = InputEdit {
+        start_byte: 11,
+        old_end_byte: 11,
+        new_end_byte: 22,
+        start_point: Point { row: 0, col: 11 },
+        old_end_point: Point { row: 0, col: 11 },
+        new_end_point: Point { row: 0, col: 22 },
+    };
+    syntax
+        .update(source.slice(..), Duration::from_secs(60), &[edit], &loader)
+        .unwrap();
+    let changed: Vec<_> = syntax.changed_ranges().collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].0, syntax.root());
+    assert!(changed[0]
+        .1
+        .iter()
+        .all(|range| range.start <= 22 && range.end >= 11));
+
+    // Replacing the comment removes its injection layer, the comment's range is
+    // reported as changed in the root layer.
+    source.remove(24..32);
+    source.insert(24, "fn b(){}");
+    let edit = InputEdit {
+        start_byte: 24,
+        old_end_byte: 32,
+        new_end_byte: 32,
+        start_point: Point { row: 1, col: 0 },
+        old_end_point: Point { row: 1, col: 8 },
+        new_end_point: Point { row: 1, col: 8 },
+    };
+    syntax
+        .update(source.slice(..), Duration::from_secs(60), &[edit], &loader)
+        .unwrap();
+    let root = syntax.layer(syntax.root());
+    assert!(root.changed_ranges().contains(&(24..32)));
+    assert_eq!(root.tree().unwrap().included_ranges().len(), 1);
+}
+
+#[test]
+fn grammar_introspection() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+
+    let identifier = grammar.id_for_node_kind("identifier", true).unwrap();
+    assert_eq!(grammar.node_kind_for_id(identifier), Some("identifier"));
+    assert!(grammar.node_kind_is_named(identifier));
+    let fn_keyword = grammar.id_for_node_kind("fn", false).unwrap();
+    assert_eq!(grammar.node_kind_for_id(fn_keyword), Some("fn"));
+    assert!(!grammar.node_kind_is_named(fn_keyword));
+    assert!(grammar.node_kind_is_visible(fn_keyword));
+    assert_eq!(grammar.id_for_node_kind("fn", true), None);
+    assert_eq!(grammar.id_for_node_kind("not_a_node", true), None);
+    assert!(u32::from(identifier) < grammar.node_kind_count());
+    assert!(grammar
+        .node_kind_for_id(grammar.node_kind_count() as u16)
+        .is_none());
+
+    let fields: Vec<_> = (1..=grammar.field_count() as u16)
+        .map(|id| {
+            grammar
+                .field_name_for_id(FieldId::new(id).unwrap())
+                .unwrap()
+        })
+        .collect();
+    assert!(fields.contains(&"body"));
+
+    // The test grammars are generated with ABI 14 which does not include metadata.
+    assert_eq!(grammar.abi_version(), 14);
+    assert_eq!(grammar.name(), None);
+    assert_eq!(grammar.version(), None);
+    assert!(grammar.supertypes().is_empty());
+    assert!(grammar.subtypes(identifier).is_empty());
+}
+
+#[test]
+fn lookahead_iterator() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str("fn main() {\n    let x = 1\n}\n");
+    let syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let root = syntax.tree().root_node();
+    let grammar = root.grammar();
+    assert!(grammar.parse_state_count() > 0);
+    assert!(grammar.lookahead_iterator(u16::MAX).is_none());
+
+    // After the `let` keyword the parser expects a pattern.
+    let let_keyword = root.descendant_for_byte_range(16, 19).unwrap();
+    assert_eq!(let_keyword.kind(), "let");
+    let state = grammar.next_parse_state(let_keyword.parse_state(), let_keyword.grammar_id());
+    assert_eq!(state, let_keyword.next_parse_state());
+    let mut lookahead = grammar.lookahead_iterator(state).unwrap();
+    assert_eq!(lookahead.grammar(), grammar);
+    let expected: Vec<_> = lookahead.names().collect();
+    assert!(expected.contains(&"identifier"));
+    assert!(!expected.contains(&"fn"));
+
+    let symbols: Vec<_> = lookahead.by_ref().collect();
+    assert!(symbols.is_empty());
+    assert!(lookahead.reset_state(state));
+    let symbols: Vec<_> = lookahead.by_ref().collect();
+    assert_eq!(symbols.len(), expected.len());
+    assert!(symbols
+        .iter()
+        .all(|&symbol| grammar.node_kind_for_id(symbol).is_some()));
+    assert!(!lookahead.reset_state(u16::MAX));
+}
+
+#[test]
+fn parse_cancellation() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str(&"fn main() { let x = 1; }\n".repeat(1000));
+    let rust = loader.get("rust");
+
+    let flag = Arc::new(AtomicBool::new(true));
+    let limit = ParseLimit::new().with_cancellation_flag(flag.clone());
+    let res = Syntax::new(source.slice(..), rust, limit.clone(), &loader);
+    assert_eq!(res.err(), Some(Error::Cancelled));
+
+    flag.store(false, Ordering::Relaxed);
+    let syntax = Syntax::new(source.slice(..), rust, limit, &loader).unwrap();
+    assert!(!syntax.tree().root_node().has_error());
+
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let offsets = Arc::new(Mutex::new(Vec::new()));
+    let offsets_ = offsets.clone();
+    parser.set_progress_callback(Some(Arc::new(move |offset| {
+        let mut offsets = offsets_.lock().unwrap();
+        offsets.push(offset);
+        offsets.len() > 3
+    })));
+    assert!(parser.parse(source.slice(..), None).is_none());
+    let offsets = offsets.lock().unwrap();
+    assert_eq!(offsets.len(), 4);
+    assert!(offsets.windows(2).all(|w| w[0] <= w[1]));
+
+    // A stopped parse resumes on the next call unless the parser is reset.
+    parser.set_progress_callback(None);
+    parser.reset();
+    let fresh = "fn fresh() {}";
+    let tree = parser.parse(fresh, None).unwrap();
+    assert_eq!(tree.root_node().byte_range(), 0..fresh.len() as u32);
+    assert!(!tree.root_node().has_error());
+}
+
+#[test]
+#[cfg(unix)]
+fn parser_debugging() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let source = Rope::from_str("fn main() {}\n");
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let messages_ = messages.clone();
+    parser.set_logger(Some(Box::new(move |log_type, message| {
+        messages_
+            .lock()
+            .unwrap()
+            .push((log_type, message.to_owned()))
+    })));
+    let tree = parser.parse(source.slice(..), None).unwrap();
+    {
+        let messages = messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|(log_type, _)| *log_type == LogType::Lex));
+        assert!(messages
+            .iter()
+            .any(|(log_type, message)| *log_type == LogType::Parse && message == "done"));
+    }
+    parser.set_logger(None);
+    messages.lock().unwrap().clear();
+    parser.parse(source.slice(..), None).unwrap();
+    assert!(messages.lock().unwrap().is_empty());
+
+    let mut graph = Vec::new();
+    tree.write_dot_graph(&mut graph).unwrap();
+    let graph = String::from_utf8(graph).unwrap();
+    assert!(graph.starts_with("digraph tree {"));
+    assert!(graph.contains("function_item"));
+
+    let path = std::env::temp_dir().join(format!("tree-house-test-{}.dot", std::process::id()));
+    let file = fs::File::create(&path).unwrap();
+    parser.print_dot_graphs(Some(file.into()));
+    parser.parse(source.slice(..), None).unwrap();
+    parser.print_dot_graphs(None);
+    let graphs = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(graphs.contains("digraph stack {"));
+}
+
+#[test]
+fn contiguous_input() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let text = "fn main() {\n    foo(\"bar\");\n    foo(\"baz\");\n}\n";
+    let owned = text.to_owned();
+
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let trees = [
+        parser.parse(text, None).unwrap(),
+        parser.parse(text.as_bytes(), None).unwrap(),
+        parser.parse(&owned, None).unwrap(),
+        parser.parse(owned.clone(), None).unwrap(),
+    ];
+    let rope = Rope::from_str(text);
+    let expected = parser.parse(rope.slice(..), None).unwrap();
+    for tree in &trees {
+        assert!(tree.changed_ranges(&expected).is_empty());
+        assert_eq!(
+            tree.root_node().byte_range(),
+            expected.root_node().byte_range()
+        );
+        assert!(!tree.root_node().has_error());
+    }
+
+    let query = Query::new(
+        grammar,
+        r#"((string_literal) @a (#match? @a "baz")) ((identifier) @b (#eq? @b "foo"))"#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let root = trees[0].root_node();
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &root, text);
+    let mut matches = Vec::new();
+    while let Some(query_match) = cursor.next_match() {
+        matches.extend(query_match.matched_nodes().map(|matched| {
+            &text[matched.node.byte_range().start as usize..matched.node.byte_range().end as usize]
+        }));
+    }
+    matches.sort_unstable();
+    assert_eq!(matches, ["\"baz\"", "foo", "foo"]);
+    // An owned `String` is moved into the cursor.
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &root, owned.clone());
+    let mut owned_matches = 0;
+    while let Some(query_match) = cursor.next_match() {
+        owned_matches += query_match.matched_nodes().count();
+    }
+    assert_eq!(owned_matches, matches.len());
+
+    let syntax = Syntax::new(text, rust, Duration::from_secs(60), &loader).unwrap();
+    assert!(syntax.tree().changed_ranges(&expected).is_empty());
+
+    // The highlighter accepts the same kinds of text.
+    let expected_highlights = highlights(&syntax, rope.slice(..), &loader);
+    assert!(!expected_highlights.is_empty());
+    assert_eq!(highlights(&syntax, text, &loader), expected_highlights);
+    assert_eq!(highlights(&syntax, &owned, &loader), expected_highlights);
+    assert_eq!(
+        highlights(&syntax, text.as_bytes(), &loader),
+        expected_highlights
+    );
+}
+
+#[test]
+fn utf16_input() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let text = "fn main() {\n    föö(\"🦀\");\n    bar(\"x\");\n}\n";
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(utf16.as_slice(), None).unwrap();
+    let root = tree.root_node();
+    assert!(!root.has_error());
+    assert_eq!(root.end_byte() as usize, utf16.len() * 2);
+    let decode = |node: &tree_sitter::Node| {
+        let range = node.byte_range();
+        String::from_utf16(&utf16[range.start as usize / 2..range.end as usize / 2]).unwrap()
+    };
+    let function = root.child(0).unwrap();
+    assert_eq!(
+        decode(&function.child_by_field_name("name").unwrap()),
+        "main"
+    );
+
+    let query = Query::new(
+        grammar,
+        r#"
+        ((identifier) @a (#eq? @a "föö"))
+        ((string_literal) @b (#match? @b "^\"\\p{Emoji}\"$"))
+        ((identifier) @c (#any-of? @c "bar" "baz"))
+        ((identifier) @d (#not-eq? @d "föö"))
+        "#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let mut cursor = InactiveQueryCursor::new().execute_query(
+        &query,
+        &root,
+        ChunkedInput::new(utf16.as_slice()),
+    );
+    let mut matches = Vec::new();
+    while let Some(query_match) = cursor.next_match() {
+        let pattern = query_match.pattern();
+        for matched in query_match.matched_nodes() {
+            matches.push((pattern.idx(), decode(&matched.node)));
+        }
+    }
+    matches.sort_unstable();
+    assert_eq!(
+        matches,
+        [
+            (0, "föö".to_owned()),
+            (1, "\"🦀\"".to_owned()),
+            (2, "bar".to_owned()),
+            (3, "bar".to_owned()),
+            (3, "main".to_owned()),
+        ]
+    );
+    // Syntax and the highlighter work on UTF-16 text as well, including injections whose
+    // language is read from the document.
+    let markdown = loader.get("markdown");
+    let text = "# Grüße\n\n```rust\nfn föö() -> &str { \"🦀\" }\n```\n";
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let syntax = Syntax::new(text, markdown, Duration::from_secs(60), &loader).unwrap();
+    let utf16_syntax =
+        Syntax::new(utf16.as_slice(), markdown, Duration::from_secs(60), &loader).unwrap();
+    assert_eq!(utf16_syntax.layers.len(), syntax.layers.len());
+    let expected: Vec<_> = highlights(&syntax, text, &loader)
+        .into_iter()
+        .map(|(offset, highlights)| {
+            let units = text[..offset as usize].encode_utf16().count();
+            (2 * units as u32, highlights)
+        })
+        .collect();
+    assert!(expected.len() > 4);
+    assert_eq!(
+        highlights(&utf16_syntax, utf16.as_slice(), &loader),
+        expected
+    );
+}
+
+/// Text split into tiny chunks, similar to a piece table.
+#[derive(Debug, Clone, Copy)]
+struct SmallChunks<'a>(&'a str);
+
+impl<'a> ChunkedText<'a> for SmallChunks<'a> {
+    fn len_bytes(self) -> usize {
+        self.0.len()
+    }
+
+    fn chunk_at_byte(self, byte_idx: usize) -> (&'a [u8], usize) {
+        const CHUNK_LEN: usize = 5;
+        let text = self.0;
+        let chunk_start = byte_idx.min(text.len().saturating_sub(1)) / CHUNK_LEN * CHUNK_LEN;
+        let mut start = chunk_start;
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = (chunk_start + CHUNK_LEN).min(text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        (&text.as_bytes()[start..end], start)
+    }
+}
+
+#[test]
+fn chunked_text() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    let text =
+        "fn main() {\n    // héllo wörld\n    let ünïcödé = \"straße\";\n    foo(ünïcödé);\n}\n";
+    let rope = Rope::from_str(text);
+    let chunked = SmallChunks(text);
+    let start = text.find("héllo").unwrap();
+    let range = start..start + "héllo wörld".len();
+    assert_eq!(chunked.byte_text(range), "héllo wörld");
+
+    let expected = Syntax::new(rope.slice(..), rust, Duration::from_secs(60), &loader).unwrap();
+    let syntax = Syntax::new(chunked, rust, Duration::from_secs(60), &loader).unwrap();
+    assert!(syntax.tree().changed_ranges(expected.tree()).is_empty());
+    assert_eq!(syntax.layers.len(), expected.layers.len());
+
+    // Predicates must handle captures spanning multiple chunks.
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let query = Query::new(
+        grammar,
+        r#"((identifier) @a (#eq? @a "ünïcödé")) ((string_literal) @b (#match? @b "ße"))"#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let root = syntax.tree().root_node();
+    let mut cursor = InactiveQueryCursor::new().execute_query(
+        &query,
+        &root,
+        tree_sitter::ChunkedInput::new(chunked),
+    );
+    let mut matches = 0;
+    while cursor.next_match().is_some() {
+        matches += 1;
+    }
+    assert_eq!(matches, 3);
+
+    let expected_highlights = highlights(&expected, rope.slice(..), &loader);
+    assert!(!expected_highlights.is_empty());
+    assert_eq!(highlights(&syntax, chunked, &loader), expected_highlights);
+    assert_eq!(highlights(&syntax, text, &loader), expected_highlights);
+}
+
+#[test]
+fn string_predicates_spanning_chunks() {
+    let loader = TestLanguageLoader::new();
+    let rust = loader.get("rust");
+    // The identifiers span several chunks and only differ in their last chunk.
+    let text = "fn f() { long_identifier_name; long_identifier_nane; long_identifier; }\n";
+    let chunked = SmallChunks(text);
+    let syntax = Syntax::new(chunked, rust, Duration::from_secs(60), &loader).unwrap();
+    let grammar = loader.get_config(rust).unwrap().grammar;
+    let root = syntax.tree().root_node();
+    let matched_identifiers = |predicate: &str| {
+        let query = Query::new(
+            grammar,
+            &format!("((identifier) @a {predicate})"),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let mut cursor = InactiveQueryCursor::new().execute_query(
+            &query,
+            &root,
+            tree_sitter::ChunkedInput::new(chunked),
+        );
+        let mut identifiers = Vec::new();
+        while let Some(m) = cursor.next_match() {
+            let range = m.matched_nodes().next().unwrap().node.byte_range();
+            identifiers.push(&text[range.start as usize..range.end as usize]);
+        }
+        identifiers
+    };
+    assert_eq!(
+        matched_identifiers(r#"(#eq? @a "long_identifier_name")"#),
+        ["long_identifier_name"]
+    );
+    assert_eq!(
+        matched_identifiers(r#"(#not-eq? @a "long_identifier_name")"#),
+        ["f", "long_identifier_nane", "long_identifier"]
+    );
+    assert_eq!(
+        matched_identifiers(r#"(#any-of? @a "long_identifier_nane" "long_identifier")"#),
+        ["long_identifier_nane", "long_identifier"]
+    );
+}
+
+#[test]
+fn query_pattern_introspection() {
+    let loader = TestLanguageLoader::new();
+    let source = Rope::from_str("// a\n// b\nfn foo() {}\n// c\nfn bar() {}\n");
+    let syntax = Syntax::new(
+        source.slice(..),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let query_source = r#"
+        (function_item "fn" name: (identifier) @name parameters: (parameters "(" ")")) @function
+        ((line_comment)+ @comments)
+        ((line_comment) @comment . (function_item) @function)
+    "#;
+    let query = Query::new(grammar, query_source, |_, _| Ok(())).unwrap();
+    let patterns: Vec<Pattern> = query.patterns().collect();
+    assert_eq!(patterns.len(), 3);
+    let capture = |name| query.get_capture(name).unwrap();
+
+    for &pattern in &patterns {
+        let start = query.start_byte_for_pattern(pattern);
+        let end = query.end_byte_for_pattern(pattern);
+        assert!(query_source[start..end].starts_with('('));
+    }
+    let pattern_start = query.start_byte_for_pattern(patterns[0]);
+    assert!(!query.is_pattern_guaranteed_at_step(pattern_start));
+    let parameters_step = query_source.find("(parameters").unwrap();
+    assert!(query.is_pattern_guaranteed_at_step(parameters_step));
+    assert!(query.is_pattern_rooted(patterns[0]));
+    assert!(!query.is_pattern_non_local(patterns[0]));
+    assert!(!query.is_pattern_rooted(patterns[2]));
+    assert!(query.is_pattern_non_local(patterns[2]));
+
+    assert_eq!(
+        query.capture_quantifier(patterns[0], capture("name")),
+        CaptureQuantifier::One
+    );
+    assert_eq!(
+        query.capture_quantifier(patterns[0], capture("comments")),
+        CaptureQuantifier::Zero
+    );
+    assert_eq!(
+        query.capture_quantifier(patterns[1], capture("comments")),
+        CaptureQuantifier::OneOrMore
+    );
+    let quantifiers: Vec<_> = query.capture_quantifiers(patterns[2]).collect();
+    assert_eq!(quantifiers.len(), query.num_captures() as usize);
+    assert!(quantifiers.contains(&(capture("comment"), CaptureQuantifier::One)));
+    assert!(quantifiers.contains(&(capture("name"), CaptureQuantifier::Zero)));
+
+    let mut text_objects = TextObjectQuery { query };
+    let root = syntax.tree().root_node();
+    let ranges = |text_objects: &TextObjectQuery, name| {
+        text_objects
+            .capture_nodes(
+                name,
+                root.clone(),
+                source.slice(..),
+                InactiveQueryCursor::new(),
+            )
+            .unwrap()
+            .map(|node| {
+                let grouped = matches!(node, CapturedNode::Grouped(_));
+                (node.start_byte()..node.end_byte(), grouped)
+            })
+            .collect::<Vec<_>>()
+    };
+    // Captures are returned in document order, a group once for each of its nodes.
+    let mut comments = ranges(&text_objects, "comments");
+    comments.dedup();
+    assert_eq!(comments, [(0..9, true), (22..26, true)]);
+    let mut functions = ranges(&text_objects, "function");
+    functions.sort_by_key(|(range, _)| range.start);
+    functions.dedup();
+    assert_eq!(functions, [(10..21, false), (27..38, false)]);
+
+    text_objects.query.disable_pattern(patterns[1]);
+    assert!(ranges(&text_objects, "comments").is_empty());
+}
+
+#[test]
+fn query_error_on_last_line() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    // The source doesn't end with a newline so there is no line after the error.
+    let err = Query::new(grammar, "(identifier) @a\n(not_a_node) @b", |_, _| Ok(())).unwrap_err();
+    let tree_sitter::query::ParseError::InvalidNodeType { node, location } = err else {
+        panic!("unexpected error {err:?}");
+    };
+    assert_eq!(node, "not_a_node");
+    assert_eq!((location.line, location.column), (1, 1));
+}
+
+#[test]
+fn node_predicates() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let text = "// TODO: fix\n// axb\n// a.b\nconst X: i32 = 1;\nfn foo() {\n    let bar = baz;\n    let s = \"str\";\n    let t = true;\n}\n";
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(text, None).unwrap();
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let utf16_tree = parser
+        .parse(ChunkedInput::new(utf16.as_slice()), None)
+        .unwrap();
+
+    let query = Query::new(
+        grammar,
+        r#"
+        ((identifier) @ancestor (#has-ancestor? @ancestor function_item))
+        ((identifier) @parent (#has-parent? @parent let_declaration))
+        ((identifier) @not_parent (#not-has-parent? @not_parent function_item))
+        ((_) @kind (#kind-eq? @kind string_literal boolean_literal))
+        ((line_comment) @contains (#contains? @contains "TODO" "a.b"))
+        "#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let mut matches = Vec::new();
+    let root = tree.root_node();
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &root, text);
+    while let Some(query_match) = cursor.next_match() {
+        matches.extend(query_match.matched_nodes().map(|matched| {
+            let range = matched.node.byte_range();
+            let text = &text[range.start as usize..range.end as usize];
+            (query.capture_name(matched.capture), text)
+        }));
+    }
+    matches.sort_unstable();
+    assert_eq!(
+        matches,
+        [
+            ("ancestor", "bar"),
+            ("ancestor", "baz"),
+            ("ancestor", "foo"),
+            ("ancestor", "s"),
+            ("ancestor", "t"),
+            ("contains", "// TODO: fix"),
+            ("contains", "// a.b"),
+            ("kind", "\"str\""),
+            ("kind", "true"),
+            ("not_parent", "X"),
+            ("not_parent", "bar"),
+            ("not_parent", "baz"),
+            ("not_parent", "s"),
+            ("not_parent", "t"),
+            ("parent", "bar"),
+            ("parent", "baz"),
+            ("parent", "s"),
+            ("parent", "t"),
+        ]
+    );
+
+    // The predicates behave the same on UTF-16 text.
+    let root = utf16_tree.root_node();
+    let mut cursor = InactiveQueryCursor::new().execute_query(
+        &query,
+        &root,
+        ChunkedInput::new(utf16.as_slice()),
+    );
+    let mut utf16_matches = 0;
+    while let Some(query_match) = cursor.next_match() {
+        utf16_matches += query_match.matched_nodes().count();
+    }
+    assert_eq!(utf16_matches, matches.len());
+
+    for invalid in [
+        "((identifier) @a (#has-parent? @a))",
+        r#"((identifier) @a (#kind-eq? "identifier" @a))"#,
+        "((identifier) @a (#contains? @a @a))",
+    ] {
+        assert!(Query::new(grammar, invalid, |_, _| Ok(())).is_err());
+    }
+}
+
+#[test]
+fn custom_predicates() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let text = "const FOO: u32 = 1;\nconst Bar: u32 = 2;\nstatic BAZ: u32 = 3;\n";
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(text, None).unwrap();
+
+    let mut registry = PredicateRegistry::new();
+    registry
+        .register("is-upper-case?", |predicate, query_match, input| {
+            let capture = predicate.capture_arg(0).unwrap();
+            query_match.nodes_for_capture(capture).all(|node| {
+                let text = input.text(node.byte_range());
+                text.chars().all(|c| !c.is_lowercase())
+            })
+        })
+        .register("parent-kind?", |predicate, query_match, _| {
+            let capture = predicate.capture_arg(0).unwrap();
+            let kind = predicate.str_arg(1).unwrap();
+            query_match
+                .nodes_for_capture(capture)
+                .all(|node| node.parent().is_some_and(|parent| parent.kind() == kind))
+        });
+    let source = r#"
+        ((identifier) @constant (#is-upper-case? @constant) (#parent-kind? @constant "const_item"))
+        ((identifier) @unknown (#unknown? @unknown))
+    "#;
+    let mut unknown = Vec::new();
+    let query = Query::with_predicates(grammar, source, &registry, |_, predicate| {
+        unknown.push(predicate.to_string());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(unknown, ["#unknown?"]);
+
+    let root = tree.root_node();
+    let constant = query.get_capture("constant").unwrap();
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &root, text);
+    let mut matches = Vec::new();
+    while let Some(query_match) = cursor.next_match() {
+        matches.extend(query_match.nodes_for_capture(constant).map(|node| {
+            let range = node.byte_range();
+            &text[range.start as usize..range.end as usize]
+        }));
+    }
+    assert_eq!(matches, ["FOO"]);
+
+    // Custom predicates are evaluated for captures and on UTF-16 text, too.
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let utf16_tree = parser
+        .parse(ChunkedInput::new(utf16.as_slice()), None)
+        .unwrap();
+    let root = utf16_tree.root_node();
+    let mut cursor =
+        cursor
+            .reuse()
+            .execute_query(&query, &root, ChunkedInput::new(utf16.as_slice()));
+    let mut captured = Vec::new();
+    while let Some((query_match, _)) = cursor.next_matched_node() {
+        captured.extend(
+            query_match
+                .nodes_for_capture(constant)
+                .map(|node| node.start_byte()),
+        );
+    }
+    assert_eq!(captured, [12]);
+
+    let query = Query::new(grammar, source, |_, predicate| {
+        Err(InvalidPredicateError::unknown(predicate))
+    });
+    assert!(query.is_err());
+}
+
+#[test]
+fn query_directives() {
+    let mut loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+
+    let text = "/*  foo  */ fn hello_world() {}";
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(text, None).unwrap();
+    let query = Query::new(
+        grammar,
+        r#"
+        ((block_comment) @comment
+         (#offset! @comment 0 2 0 -2)
+         (#trim! @comment 0 1 0 1))
+        ((identifier) @name
+         (#gsub! @name "^([a-z]+)_([a-z]+)$" "$2-$1")
+         (#strip! @name "o"))
+        "#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let root = tree.root_node();
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &root, text);
+    let mut results = Vec::new();
+    while let Some(query_match) = cursor.next_match() {
+        for matched_node in query_match.matched_nodes() {
+            let node_range = matched_node.node.byte_range();
+            let range = query.capture_range(
+                query_match.pattern(),
+                matched_node.capture,
+                node_range.clone(),
+                &mut ChunkedInput::new(text),
+            );
+            let node_text = &text[node_range.start as usize..node_range.end as usize];
+            let captured_text = query.capture_text(
+                query_match.pattern(),
+                matched_node.capture,
+                node_text.into(),
+            );
+            results.push((
+                &text[range.start as usize..range.end as usize],
+                captured_text.into_owned(),
+            ));
+        }
+    }
+    assert_eq!(
+        results,
+        [
+            ("foo", "/*  foo  */".to_owned()),
+            ("hello_world", "wrld-hell".to_owned()),
+        ]
+    );
+
+    // #trim! computes offsets in the units of the input, also for UTF-16 and multibyte chars.
+    let text = "/*\n  ünï 🦀\r\n\n  */";
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let tree = parser.parse(text, None).unwrap();
+    let utf16_tree = parser.parse(utf16.as_slice(), None).unwrap();
+    for (flags, expected) in [
+        ("1 0 1 0", "  ünï 🦀"),
+        ("0 1 0 1", "ünï 🦀"),
+        ("1 0 0 1", "  ünï 🦀"),
+        ("0 0 1 0", "\n  ünï 🦀"),
+    ] {
+        let query = Query::new(
+            grammar,
+            &format!("((block_comment) @c (#trim! @c {flags}))"),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let pattern = query.patterns().next().unwrap();
+        let capture = query.get_capture("c").unwrap();
+        // Trim the text between the comment delimiters.
+        let range = tree.root_node().child(0).unwrap().byte_range();
+        let range = range.start + 2..range.end - 2;
+        let range = query.capture_range(pattern, capture, range, &mut ChunkedInput::new(text));
+        assert_eq!(&text[range.start as usize..range.end as usize], expected);
+        let range = utf16_tree.root_node().child(0).unwrap().byte_range();
+        let range = range.start + 4..range.end - 4;
+        let range = query.capture_range(
+            pattern,
+            capture,
+            range,
+            &mut ChunkedInput::new(utf16.as_slice()),
+        );
+        let trimmed = &utf16[range.start as usize / 2..range.end as usize / 2];
+        assert_eq!(String::from_utf16(trimmed).unwrap(), expected);
+    }
+
+    // #offset! moves by code units of the input and keeps the column when moving rows.
+    let text = "  /* ü\n  ab\n  */ /* 🦀 */";
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    let tree = parser.parse(text, None).unwrap();
+    let utf16_tree = parser.parse(utf16.as_slice(), None).unwrap();
+    for (offsets, comment, expected) in [
+        ("0 3 0 -3", 1, "🦀"),
+        ("1 0 -1 0", 0, "ab"),
+        ("0 3 1 0", 0, "ü\n  ab\n  */"),
+        ("-1 0 1 0", 1, "/* 🦀 */"),
+    ] {
+        let query = Query::new(
+            grammar,
+            &format!("((block_comment) @c (#offset! @c {offsets}))"),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let pattern = query.patterns().next().unwrap();
+        let capture = query.get_capture("c").unwrap();
+        let range = tree.root_node().child(comment).unwrap().byte_range();
+        let range = query.capture_range(pattern, capture, range, &mut ChunkedInput::new(text));
+        assert_eq!(&text[range.start as usize..range.end as usize], expected);
+        let range = utf16_tree.root_node().child(comment).unwrap().byte_range();
+        let range = query.capture_range(
+            pattern,
+            capture,
+            range,
+            &mut ChunkedInput::new(utf16.as_slice()),
+        );
+        let moved = &utf16[range.start as usize / 2..range.end as usize / 2];
+        assert_eq!(String::from_utf16(moved).unwrap(), expected, "{offsets}");
+    }
+
+    for invalid in [
+        "((identifier) @a (#offset! @a 0 x 0 0))",
+        "((identifier) @a (#offset! @a 0 0 0))",
+        "((identifier) @a (#trim! @a 1 1))",
+        "((identifier) @a (#trim! @a 1 1 2 1))",
+        r#"((identifier) @a (#gsub! @a "(" ""))"#,
+        "((identifier) @a (#strip!))",
+    ] {
+        assert!(
+            Query::new(grammar, invalid, |_, _| Ok(())).is_err(),
+            "{invalid}"
+        );
+    }
+
+    // Directives narrow the ranges of injections and transform injection language names.
+    loader.overwrite_injections(
+        "rust",
+        r#"
+((macro_invocation
+   macro: (identifier) @injection.language
+   (token_tree) @injection.content)
+ (#gsub! @injection.language "_template$" "")
+ (#set! injection.include-children)
+ (#offset! @injection.content 0 1 0 -1))"#
+            .to_owned(),
+    );
+    // Directives also narrow highlight spans.
+    loader.overwrite_highlights(
+        "rust",
+        r#"
+((string_literal) @string (#offset! @string 0 1 0 -1))
+(identifier) @variable
+"#
+        .to_owned(),
+    );
+    let source = "html_template!(<b>hi</b>); let s = \"abc\";";
+    let syntax = Syntax::new(source, loader.get("rust"), Duration::from_secs(60), &loader).unwrap();
+    let root_layer = syntax.layer(syntax.root());
+    let injection = root_layer
+        .injection_at_byte_idx(source.find('<').unwrap() as u32)
+        .unwrap();
+    assert_eq!(syntax.layer(injection.layer).language, loader.get("html"));
+    assert_eq!(
+        &source[injection.range.start as usize..injection.range.end as usize],
+        "<b>hi</b>"
+    );
+
+    let mut highlighter = Highlighter::new(&syntax, source, &loader, ..);
+    let mut active = Vec::new();
+    let mut spans = Vec::new();
+    while highlighter.next_event_offset() != u32::MAX {
+        let start = highlighter.next_event_offset();
+        let (event, highlights) = highlighter.advance();
+        if event == HighlightEvent::Refresh {
+            active.clear();
+        }
+        active.extend(highlights);
+        let end = highlighter.next_event_offset().min(source.len() as u32);
+        if let Some(&highlight) = active.last() {
+            spans.push((
+                &source[start as usize..end as usize],
+                loader.test_theme.borrow()[highlight.idx()].clone(),
+            ));
+        }
+    }
+    assert!(spans.contains(&("abc", "string".to_owned())));
+    assert!(spans.contains(&("s", "variable".to_owned())));
+    assert!(!spans.iter().any(|(text, _)| text.contains('"')));
+}
+
+#[test]
+fn query_cursor_limits() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let text = "fn a() {\n    fn b() {}\n}\nfn c() {}\n";
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(text, None).unwrap();
+    let root = tree.root_node();
+    let query = Query::new(
+        grammar,
+        "(function_item name: (identifier) @name)",
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let names = |configure: &dyn Fn(&mut InactiveQueryCursor)| {
+        let mut cursor = InactiveQueryCursor::new();
+        configure(&mut cursor);
+        let mut cursor = cursor.execute_query(&query, &root, text);
+        let mut names = Vec::new();
+        while let Some(query_match) = cursor.next_match() {
+            let node = &query_match.matched_node(0).node;
+            names.push(&text[node.start_byte() as usize..node.end_byte() as usize]);
+        }
+        names
+    };
+    assert_eq!(names(&|_| ()), ["a", "b", "c"]);
+    assert_eq!(
+        names(&|cursor| cursor
+            .set_point_range(Point { row: 1, col: 0 }..Point { row: 2, col: 0 })
+            .unwrap()),
+        ["a", "b"]
+    );
+    let mut cursor = InactiveQueryCursor::new();
+    assert_eq!(
+        cursor.set_point_range(Point { row: 2, col: 0 }..Point { row: 1, col: 0 }),
+        Err(InvalidRangeError)
+    );
+    assert_eq!(
+        cursor.set_byte_range(std::ops::Range { start: 2, end: 1 }),
+        Err(InvalidRangeError)
+    );
+    // `fn a` intersects the range but isn't contained in it.
+    assert_eq!(
+        names(&|cursor| cursor.set_containing_byte_range(Some(9..text.len() as u32))),
+        ["b", "c"]
+    );
+    assert_eq!(
+        names(&|cursor| cursor.set_max_start_depth(Some(0))),
+        [""; 0]
+    );
+    assert_eq!(
+        names(&|cursor| cursor.set_max_start_depth(Some(1))),
+        ["a", "c"]
+    );
+
+    // The timeout and progress callback are only checked periodically so they need a
+    // larger input.
+    let text = "fn f() {}\n".repeat(1000);
+    let tree = parser.parse(&text, None).unwrap();
+    let root = tree.root_node();
+    let count_matches = |cursor: InactiveQueryCursor| {
+        let mut cursor = cursor.execute_query(&query, &root, text.as_str());
+        let mut matches = 0;
+        while cursor.next_match().is_some() {
+            matches += 1;
+        }
+        (matches, cursor.was_cancelled())
+    };
+    assert_eq!(count_matches(InactiveQueryCursor::new()), (1000, false));
+    let mut cursor = InactiveQueryCursor::new();
+    cursor.set_timeout(Some(Duration::ZERO));
+    let (matches, cancelled) = count_matches(cursor);
+    assert!(cancelled && matches < 1000);
+    let mut cursor = InactiveQueryCursor::new();
+    cursor.set_progress_callback(Some(Arc::new(|offset| offset > 5000)));
+    let (matches, cancelled) = count_matches(cursor);
+    assert!(cancelled && (500..1000).contains(&matches), "{matches}");
+
+    // The same limits are available for `QueryIter`.
+    struct SingleQueryLoader<'a>(&'a Query);
+    impl<'a> QueryLoader<'a> for SingleQueryLoader<'a> {
+        fn get_query(&mut self, _lang: Language) -> Option<&'a Query> {
+            Some(self.0)
+        }
+    }
+    let syntax = Syntax::new(
+        text.as_str(),
+        loader.get("rust"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let iter_matches = |options: QueryIterOptions| {
+        let iter: QueryIter<'_, '_, _, _> = QueryIter::with_options(
+            &syntax,
+            text.as_str(),
+            SingleQueryLoader(&query),
+            ..,
+            options,
+        );
+        iter.filter(|event| matches!(event, QueryIterEvent::Match(_)))
+            .count()
+    };
+    assert_eq!(iter_matches(QueryIterOptions::default()), 1000);
+    assert_eq!(
+        iter_matches(QueryIterOptions {
+            point_range: Some(Point { row: 10, col: 0 }..Point { row: 20, col: 0 }),
+            ..QueryIterOptions::default()
+        }),
+        10
+    );
+    assert_eq!(
+        iter_matches(QueryIterOptions {
+            containing_range: Some(0..33),
+            ..QueryIterOptions::default()
+        }),
+        3
+    );
+    assert_eq!(
+        iter_matches(QueryIterOptions {
+            max_start_depth: Some(0),
+            ..QueryIterOptions::default()
+        }),
+        0
+    );
+    assert!(
+        iter_matches(QueryIterOptions {
+            progress_callback: Some(Arc::new(|offset| offset > 5000)),
+            ..QueryIterOptions::default()
+        }) < 1000
+    );
+}
+
+#[test]
+fn query_captures() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let text = "fn a(b: u8) {} fn c() {}";
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let tree = parser.parse(text, None).unwrap();
+    let query = Query::new(
+        grammar,
+        r#"
+        (function_item name: (identifier) @function body: (block) @body)
+        ((identifier) @variable (#not-eq? @variable "c"))
+        "#,
+        |_, _| Ok(()),
+    )
+    .unwrap();
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &tree.root_node(), text);
+    let mut captures = Vec::new();
+    let mut iter = cursor.captures();
+    while let Some((query_match, capture_idx)) = iter.next() {
+        let matched_node = query_match.matched_node(capture_idx);
+        let range = matched_node.node.byte_range();
+        captures.push((
+            query.capture_name(matched_node.capture),
+            &text[range.start as usize..range.end as usize],
+            query_match.pattern().idx(),
+        ));
+    }
+    // Unlike matches, captures are sorted by their start byte.
+    assert_eq!(
+        captures,
+        [
+            ("function", "a", 0),
+            ("variable", "a", 1),
+            ("variable", "b", 1),
+            ("body", "{}", 0),
+            ("function", "c", 0),
+            ("body", "{}", 0),
+        ]
+    );
+
+    // Removing a match skips its remaining captures.
+    let mut cursor = InactiveQueryCursor::new().execute_query(&query, &tree.root_node(), text);
+    let mut captures = cursor.captures();
+    let mut bodies = 0;
+    while let Some((query_match, capture_idx)) = captures.next() {
+        let capture = query_match.matched_node(capture_idx).capture;
+        match query.capture_name(capture) {
+            "function" => query_match.remove(),
+            "body" => bodies += 1,
+            _ => (),
+        }
+    }
+    assert_eq!(bodies, 0);
+}
+
+#[test]
+fn owned_nodes() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let text = "fn foo() { bar(); }";
+    let tree = parser.parse(text, None).unwrap();
+    let call = tree
+        .root_node()
+        .descendant_for_byte_range(11, 14)
+        .unwrap()
+        .parent()
+        .unwrap();
+    assert_eq!(call.kind(), "call_expression");
+    let owned = call.to_owned_node();
+    let shared = Arc::new(tree.clone());
+    let shared_call = shared
+        .root_node()
+        .descendant_for_byte_range(11, 16)
+        .unwrap();
+    assert_eq!(OwnedNode::new(shared.clone(), &shared_call), owned);
+    assert_eq!(
+        OwnedNode::new(shared.clone(), &shared.root_node())
+            .node()
+            .kind(),
+        "source_file"
+    );
+    let root = tree.root_node().to_owned_node();
+    drop(tree);
+    assert_eq!(root.node().kind(), "source_file");
+    assert_eq!(root.node().child(0).unwrap().kind(), "function_item");
+
+    // The handle outlives the original tree and can be sent to other threads.
+    let handle = std::thread::spawn(move || {
+        let node = owned.node();
+        assert_eq!(node.kind(), "call_expression");
+        assert_eq!(node.byte_range(), 11..16);
+        assert_eq!(node.parent().unwrap().kind(), "expression_statement");
+        owned
+    });
+    let owned = handle.join().unwrap();
+
+    // Resolve the node in a reparsed tree after an edit behind the node.
+    let new_text = "fn foo() { bar(); baz(); }";
+    let mut old_tree = owned.tree().as_ref().clone();
+    old_tree.edit(&InputEdit {
+        start_byte: 18,
+        old_end_byte: 18,
+        new_end_byte: 25,
+        start_point: Point { row: 0, col: 18 },
+        old_end_point: Point { row: 0, col: 18 },
+        new_end_point: Point { row: 0, col: 25 },
+    });
+    let new_tree = parser.parse(new_text, Some(&old_tree)).unwrap();
+    let resolved = owned.resolve(&new_tree).unwrap();
+    assert_eq!(resolved.kind(), "call_expression");
+    assert_eq!(resolved.byte_range(), 11..16);
+    // The text before `baz()` changed so it needs the mapped range.
+    let baz = new_tree
+        .root_node()
+        .descendant_for_byte_range(18, 21)
+        .unwrap();
+    let baz = baz.parent().unwrap().to_owned_node();
+    let newer_text = format!("  {new_text}");
+    let newer_tree = parser.parse(&newer_text, None).unwrap();
+    assert!(baz.resolve(&newer_tree).is_none());
+    assert_eq!(
+        baz.resolve_at(&newer_tree, 20..25).unwrap().kind(),
+        "call_expression"
+    );
+}
+
+#[test]
+fn tree_cursor_positions() {
+    let loader = TestLanguageLoader::new();
+    let grammar = loader.get_config(loader.get("rust")).unwrap().grammar;
+    let mut parser = Parser::new();
+    parser.set_grammar(grammar).unwrap();
+    let text = "fn a(b: u8) {}";
+    let tree = parser.parse(text, None).unwrap();
+
+    // A pre-order traversal visits the descendants in the order of their index.
+    let mut nodes = Vec::new();
+    walk_tree(&tree, |cursor| {
+        assert_eq!(cursor.descendant_index(), nodes.len() as u32);
+        nodes.push((cursor.node(), cursor.depth()));
+    });
+    assert_eq!(nodes[0].0.kind(), "source_file");
+    assert_eq!(nodes[1], (tree.root_node().child(0).unwrap(), 1));
+    let mut cursor = tree.walk();
+    for (i, (node, depth)) in nodes.iter().enumerate().rev() {
+        cursor.goto_descendant(i as u32);
+        assert_eq!(cursor.node(), *node);
+        assert_eq!(cursor.depth(), *depth);
+    }
+
+    let parameter = nodes
+        .iter()
+        .find(|(node, _)| node.kind() == "parameter")
+        .unwrap();
+    cursor.goto_descendant(0);
+    let mut saved = tree.walk();
+    saved.goto_descendant(nodes.iter().position(|n| n == parameter).unwrap() as u32);
+    cursor.reset_to(&saved);
+    assert_eq!(cursor.node(), parameter.0);
+    assert!(cursor.goto_parent());
+    assert_eq!(cursor.node().kind(), "parameters");
+    assert_eq!(cursor.field_name(), Some("parameters"));
+    cursor.reset(&parameter.0);
+    assert_eq!(cursor.depth(), 0);
+    assert!(!cursor.goto_parent());
+
+    // The cursor of the highlighter crate works across injection layers.
+    let source = "```rust\nfn a() {}\n```\n";
+    let syntax = Syntax::new(
+        source,
+        loader.get("markdown"),
+        Duration::from_secs(60),
+        &loader,
+    )
+    .unwrap();
+    let name = source.find("a(").unwrap() as u32;
+    let mut cursor = syntax.walk();
+    cursor.reset_to_byte_range(name, name + 1);
+    assert_eq!(cursor.node().kind(), "identifier");
+    assert_eq!(cursor.field_name(), Some("name"));
+    assert_eq!(syntax.layer(cursor.layer()).language, loader.get("rust"));
+    let depth = cursor.depth();
+    let (layer, index) = (cursor.layer(), cursor.descendant_index());
+    let saved = cursor.clone();
+    let mut parents = 0;
+    while cursor.goto_parent() {
+        parents += 1;
+    }
+    assert_eq!(parents, depth);
+    assert_eq!(cursor.depth(), 0);
+    assert_eq!(cursor.layer(), syntax.root());
+
+    cursor.reset_to(&saved);
+    assert_eq!(cursor.node().kind(), "identifier");
+    cursor.reset();
+    assert_eq!(cursor.node(), syntax.tree().root_node());
+    assert!(cursor.goto_descendant(layer, index));
+    assert_eq!(cursor.node().kind(), "identifier");
+    assert_eq!(cursor.depth(), depth);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_parsing() {
+    let loader = TestLanguageLoader::new();
+    let mut source = String::from("# Code\n\n");
+    for i in 0..24 {
+        source.push_str(&format!(
+            "Block *{i}*:\n\n```rust\nfn f{i}(x: u32) -> u32 {{ x + {i} }}\n```\n\n"
+        ));
+    }
+    // Describes all layers and their syntax trees independently of the layer ids.
+    fn describe(syntax: &Syntax) -> Vec<String> {
+        let mut layers: Vec<_> = syntax
+            .layers
+            .iter()
+            .map(|(_, layer)| {
+                let mut description = format!("{:?} {:?}", layer.language, layer.ranges);
+                walk_tree(layer.tree().unwrap(), |cursor| {
+                    let node = cursor.node();
+                    description.push_str(&format!(" {}{:?}", node.kind(), node.byte_range()));
+                });
+                description
+            })
+            .collect();
+        layers.sort();
+        layers
+    }
+    let threads = std::num::NonZeroUsize::new(4).unwrap();
+    let pool = Arc::new(crate::ParsePool::new(threads).unwrap());
+    let sequential_limit = ParseLimit::new().with_timeout(Duration::from_secs(60));
+    let parallel_limit = sequential_limit.clone().with_parse_pool(pool);
+    let markdown = loader.get("markdown");
+    let mut sequential =
+        Syntax::new(source.as_str(), markdown, sequential_limit.clone(), &loader).unwrap();
+    let mut parallel =
+        Syntax::new(source.as_str(), markdown, parallel_limit.clone(), &loader).unwrap();
+    let description = describe(&sequential);
+    assert!(
+        description
+            .iter()
+            .filter(|layer| layer.contains("function_item"))
+            .count()
+            == 24
+    );
+    assert_eq!(describe(&parallel), description);
+
+    // Edit two of the code blocks so that they are reparsed in the same batch.
+    let mut edits = Vec::new();
+    for (i, replacement) in [(3, "x - 3"), (20, "x * 20")] {
+        let pattern = format!("x + {i} ");
+        let start = source.find(&pattern).unwrap();
+        let end = start + pattern.len() - 1;
+        let position = |byte: usize| Point {
+            row: source[..byte].matches('\n').count() as u32,
+            col: (byte - source[..byte].rfind('\n').map_or(0, |i| i + 1)) as u32,
+        };
+        let edit = InputEdit {
+            start_byte: start as u32,
+            old_end_byte: end as u32,
+            new_end_byte: (start + replacement.len()) as u32,
+            start_point: position(start),
+            old_end_point: position(end),
+            new_end_point: Point {
+                row: position(start).row,
+                col: position(start).col + replacement.len() as u32,
+            },
+        };
+        source.replace_range(start..end, replacement);
+        edits.push(edit);
+    }
+    sequential
+        .update(source.as_str(), sequential_limit.clone(), &edits, &loader)
+        .unwrap();
+    parallel
+        .update(source.as_str(), parallel_limit, &edits, &loader)
+        .unwrap();
+    let description = describe(&sequential);
+    assert_eq!(
+        description,
+        describe(&Syntax::new(source.as_str(), markdown, sequential_limit, &loader).unwrap())
+    );
+    assert_eq!(describe(&parallel), description);
+}
+
+#[test]
+fn background_update() {
+    let loader = TestLanguageLoader::new();
+    let mut source = Rope::from_str("# Title\n\n```rust\nfn f() {}\n```\n");
+    // Describes the root tree and the number of layers of a snapshot.
+    fn describe(syntax: &Syntax) -> String {
+        let mut description = format!("{} layers:", syntax.layers.len());
+        walk_tree(syntax.tree(), |cursor| {
+            let node = cursor.node();
+            description.push_str(&format!(" {}{:?}", node.kind(), node.byte_range()));
+        });
+        description
+    }
+    // Appends text to the end of the source and returns the corresponding edit.
+    fn append(source: &mut Rope, text: &str) -> InputEdit {
+        let end = source.len_bytes() as u32;
+        let end_point = Point {
+            row: source.len_lines() as u32 - 1,
+            col: 0,
+        };
+        source.insert(source.len_chars(), text);
+        InputEdit {
+            start_byte: end,
+            old_end_byte: end,
+            new_end_byte: source.len_bytes() as u32,
+            start_point: end_point,
+            old_end_point: end_point,
+            new_end_point: Point {
+                row: source.len_lines() as u32 - 1,
+                col: 0,
+            },
+        }
+    }
+    let markdown = loader.get("markdown");
+    let syntax = Syntax::new(&source, markdown, Duration::from_secs(60), &loader).unwrap();
+    let shared = SharedSyntax::new(syntax);
+    let old = shared.load();
+    let old_description = describe(&old);
+
+    let edit = append(&mut source, "\n```rust\nfn g() {}\n```\n");
+    let update = shared.update_in_background(
+        source.clone(),
+        Duration::from_secs(60),
+        vec![edit],
+        Box::new(TestLanguageLoader::new()),
+    );
+    assert_eq!(update.wait(), Some(Ok(UpdateReport::default())));
+    // Readers holding on to the old snapshot are unaffected by the update.
+    assert_eq!(describe(&old), old_description);
+    let expected = Syntax::new(&source, markdown, Duration::from_secs(60), &loader).unwrap();
+    let new = shared.load();
+    assert_ne!(describe(&new), old_description);
+    assert_eq!(describe(&new), describe(&expected));
+
+    // A failed update does not publish anything and its edits are applied by the next update.
+    let edit = append(&mut source, &"\nparagraph\n".repeat(1000));
+    let cancelled = ParseLimit::new().with_cancellation_flag(Arc::new(AtomicBool::new(true)));
+    let mut update = shared.update_in_background(
+        source.clone(),
+        cancelled,
+        vec![edit],
+        Box::new(TestLanguageLoader::new()),
+    );
+    let res = loop {
+        if let Some(res) = update.try_wait() {
+            break res;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(res, Err(Error::Cancelled));
+    assert_eq!(update.wait(), None);
+    assert_eq!(describe(&shared.load()), describe(&new));
+    let edit = append(&mut source, "\n```rust\nfn h() {}\n```\n");
+    let update = shared.update_in_background(
+        source.clone(),
+        Duration::from_secs(60),
+        vec![edit],
+        Box::new(TestLanguageLoader::new()),
+    );
+    assert_eq!(update.wait(), Some(Ok(UpdateReport::default())));
+    let expected = Syntax::new(&source, markdown, Duration::from_secs(60), &loader).unwrap();
+    let new = shared.load();
+    assert_eq!(describe(&new), describe(&expected));
+    // The previous tree was reused: a parse from scratch reports the whole document as changed.
+    let changed = new.layer(new.root()).changed_ranges();
+    assert!(!changed.is_empty());
+    assert!(changed.iter().all(|range| range.start > 0));
+}
+
+#[test]
+fn partial_update_on_timeout() {
+    let mut loader = TestLanguageLoader::new();
+    loader.overwrite_injections(
+        "rust",
+        r#"((line_comment) @injection.content (#set! injection.language "comment"))"#.to_owned(),
+    );
+    let rust = loader.get("rust");
+    let comment = loader.get("comment");
+    // The parser only checks the timeout every 100 operations: the rust layer lexes the
+    // comment as a single token and is parsed before the timeout is checked while the comment
+    // layer always exceeds it.
+    let tiny_timeout = Duration::from_nanos(1);
+    let mut source = format!("// {}\nfn f() {{}}\n", "word ".repeat(1000));
+    let comment_layer = |syntax: &Syntax| {
+        let layers: Vec<_> = syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| layer.language == comment)
+            .map(|(idx, _)| Layer(idx as u32))
+            .collect();
+        assert_eq!(layers.len(), 1);
+        layers[0]
+    };
+
+    let mut syntax = Syntax::new(source.as_str(), rust, tiny_timeout, &loader).unwrap();
+    let layer = comment_layer(&syntax);
+    assert!(syntax.layer(layer).is_stale());
+    assert!(syntax.layer(layer).tree().is_none());
+    assert!(!syntax.layer(syntax.root()).is_stale());
+    assert_eq!(syntax.tree().root_node().kind(), "source_file");
+
+    // Stale layers are reparsed by the next update even without edits.
+    let report = syntax
+        .update(source.as_str(), Duration::from_secs(60), &[], &loader)
+        .unwrap();
+    assert!(report.is_complete());
+    let layer = comment_layer(&syntax);
+    assert!(!syntax.layer(layer).is_stale());
+    assert!(syntax.layer(layer).tree().is_some());
+
+    // A timed out layer keeps its previous tree and the rest of the update is applied.
+    let edit = InputEdit {
+        start_byte: 3,
+        old_end_byte: 7,
+        new_end_byte: 7,
+        start_point: Point { row: 0, col: 3 },
+        old_end_point: Point { row: 0, col: 7 },
+        new_end_point: Point { row: 0, col: 7 },
+    };
+    source.replace_range(3..7, "text");
+    let report = syntax
+        .update(source.as_str(), tiny_timeout, &[edit], &loader)
+        .unwrap();
+    let layer = comment_layer(&syntax);
+    assert_eq!(report.stale_layers, vec![layer]);
+    assert!(syntax.layer(layer).is_stale());
+    assert!(syntax.layer(layer).tree().is_some());
+    assert!(!syntax.layer(layer).changed_ranges().is_empty());
+    assert!(!syntax.layer(syntax.root()).is_stale());
+
+    // A timeout of the root layer still fails the update.
+    let source = "fn f() {}\n".repeat(100);
+    assert_eq!(
+        Syntax::new(source.as_str(), rust, tiny_timeout, &loader).err(),
+        Some(Error::Timeout)
+    );
+}
+
+#[test]
+fn deferred_injections() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let rust = loader.get("rust");
+    let mut source = String::from("# Code\n\n");
+    for i in 0..8 {
+        source.push_str(&format!("```rust\nfn f{i}() {{}}\n```\n\n"));
+    }
+    let parsed_rust_layers = |syntax: &Syntax| {
+        let mut parsed: Vec<_> = syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| layer.language == rust)
+            .map(|(_, layer)| {
+                assert_eq!(layer.is_deferred(), layer.tree().is_none());
+                (layer.ranges[0].start_byte, layer.tree().is_some())
+            })
+            .collect();
+        parsed.sort();
+        parsed
+            .into_iter()
+            .map(|(_, parsed)| parsed)
+            .collect::<Vec<_>>()
+    };
+    let full = Syntax::new(source.as_str(), markdown, Duration::from_secs(60), &loader).unwrap();
+
+    // Only the code block inside of the priority range is parsed right away.
+    let block = source.find("fn f2").unwrap() as u32;
+    let limit = Duration::from_secs(60);
+    let mut syntax =
+        Syntax::new_prioritized(source.as_str(), markdown, limit, block..block + 1, &loader)
+            .unwrap();
+    assert_eq!(
+        parsed_rust_layers(&syntax),
+        [false, false, true, false, false, false, false, false]
+    );
+    assert_ne!(
+        highlights(&syntax, &source, &loader),
+        highlights(&full, &source, &loader)
+    );
+
+    // Updates parse the deferred layers which intersect the new priority range.
+    let block = source.find("fn f5").unwrap() as u32;
+    let report = syntax
+        .update_prioritized(source.as_str(), limit, &[], block..block + 1, &loader)
+        .unwrap();
+    // The markdown-inline layer of the heading is deferred as well.
+    assert_eq!(report.deferred_layers.len(), 7);
+    assert!(report.stale_layers.is_empty());
+    assert_eq!(
+        parsed_rust_layers(&syntax),
+        [false, false, true, false, false, true, false, false]
+    );
+
+    // Without any budget nothing is parsed.
+    let report = syntax
+        .parse_deferred(source.as_str(), Duration::ZERO, &loader)
+        .unwrap();
+    assert_eq!(report.deferred_layers.len(), 7);
+    let report = syntax
+        .parse_deferred(source.as_str(), Duration::from_secs(60), &loader)
+        .unwrap();
+    assert!(report.is_complete());
+    assert_eq!(parsed_rust_layers(&syntax), [true; 8]);
+    assert_eq!(syntax.layers.len(), full.layers.len());
+    assert_eq!(
+        highlights(&syntax, &source, &loader),
+        highlights(&full, &source, &loader)
+    );
+}
+
+#[test]
+fn deferred_layer_out_of_budget() {
+    let loader = TestLanguageLoader::new();
+    let markdown = loader.get("markdown");
+    let rust = loader.get("rust");
+    // The first code block is too large to be parsed within the budget.
+    let mut source = String::from("```rust\nfn large() {\n");
+    source.push_str(&"    let x = [1, 2, 3].iter().map(|x| x + 1).sum::<u32>();\n".repeat(20_000));
+    source.push_str("}\n```\n\n");
+    for i in 0..4 {
+        source.push_str(&format!("```rust\nfn f{i}() {{}}\n```\n\n"));
+    }
+    let end = source.len() as u32;
+    let parsed_rust_layers = |syntax: &Syntax| {
+        syntax
+            .layers
+            .iter()
+            .filter(|(_, layer)| layer.language == rust && layer.tree().is_some())
+            .count()
+    };
+    let mut syntax = Syntax::new_prioritized(
+        source.as_str(),
+        markdown,
+        Duration::from_secs(60),
+        end..end,
+        &loader,
+    )
+    .unwrap();
+    assert_eq!(parsed_rust_layers(&syntax), 0);
+
+    let budget = Duration::from_millis(1);
+    let report = syntax
+        .parse_deferred(source.as_str(), budget, &loader)
+        .unwrap();
+    assert_eq!(report.deferred_layers.len(), 5);
+    assert_eq!(parsed_rust_layers(&syntax), 0);
+    // The large block is retried after the others so they are parsed by the next calls.
+    syntax
+        .parse_deferred(source.as_str(), budget, &loader)
+        .unwrap();
+    assert!(parsed_rust_layers(&syntax) > 0);
+    let report = syntax
+        .parse_deferred(source.as_str(), Duration::from_secs(60), &loader)
+        .unwrap();
+    assert!(report.is_complete());
+    assert_eq!(parsed_rust_layers(&syntax), 5);
+}
+
+/// Random rust documents for the `InputEdit` property tests. Every line is a function which
+/// returns a string so the documents always parse without errors and incremental parses must
+/// match fresh parses exactly.
+mod edit_props {
+    use std::ops::Range;
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    const IDENTS: [&str; 5] = ["a", "é", "日本", "b_2", "xyz"];
+    const STRINGS: [&str; 4] = ["", "ü", "€\nline", "a\n\nb"];
+
+    pub type Line = (usize, usize);
+
+    pub fn render(lines: &[Line]) -> String {
+        lines
+            .iter()
+            .map(|&(ident, string)| {
+                format!("fn {}() {{ \"{}\" }}\n", IDENTS[ident], STRINGS[string])
+            })
+            .collect()
+    }
+
+    fn line() -> impl Strategy<Value = Line> {
+        (0..IDENTS.len(), 0..STRINGS.len())
+    }
+
+    /// Returns a random change for each of up to `len` lines.
+    pub fn ops(len: usize) -> impl Strategy<Value = Vec<(u8, Line)>> {
+        vec((0..6u8, line()), len)
+    }
+
+    /// Returns random lines and a random change for each line.
+    pub fn lines_and_ops() -> impl Strategy<Value = (Vec<Line>, Vec<(u8, Line)>)> {
+        (vec(line(), 0..8), ops(8))
+    }
+
+    /// Applies `ops` to `lines` and returns the new lines together with the changes in chars
+    /// of the old text.
+    pub fn apply(lines: &[Line], ops: &[(u8, Line)]) -> (Vec<Line>, Vec<(Range<usize>, String)>) {
+        let mut new_lines = Vec::new();
+        let mut changes = Vec::new();
+        let mut pos = 0;
+        for (&line, &(op, (ident, string))) in lines.iter().zip(ops) {
+            let len = render(&[line]).chars().count();
+            let ident_start = pos + "fn ".len();
+            let ident_end = ident_start + IDENTS[line.0].chars().count();
+            let string_start = ident_end + "() { \"".len();
+            let string_end = string_start + STRINGS[line.1].chars().count();
+            match op {
+                0 => new_lines.push(line),
+                1 => {
+                    changes.push((ident_start..ident_end, IDENTS[ident].to_owned()));
+                    new_lines.push((ident, line.1));
+                }
+                2 => {
+                    changes.push((string_start..string_end, STRINGS[string].to_owned()));
+                    new_lines.push((line.0, string));
+                }
+                3 => changes.push((pos..pos + len, String::new())),
+                4 => {
+                    changes.push((pos..pos, render(&[(ident, string)])));
+                    new_lines.push((ident, string));
+                    new_lines.push(line);
+                }
+                // The same as renaming but split into two changes which touch.
+                _ => {
+                    let (first, rest) =
+                        IDENTS[ident].split_at(IDENTS[ident].chars().next().unwrap().len_utf8());
+                    changes.push((ident_start..ident_end, first.to_owned()));
+                    changes.push((ident_end..ident_end, rest.to_owned()));
+                    new_lines.push((ident, line.1));
+                }
+            }
+            pos += len;
+        }
+        (new_lines, changes)
+    }
+}
+
+/// Checks the `edits` between `old_text` and `new_text`: the edited tree of `old_text` must
+/// have the points of `new_text` outside of the edits and updating the syntax must result in
+/// the same tree as a fresh parse of `new_text`.
+fn check_incremental_parse(
+    loader: &TestLanguageLoader,
+    old_text: &Rope,
+    new_text: &Rope,
+    edits: &[InputEdit],
+) -> Result<(), proptest::test_runner::TestCaseError> {
+    fn nodes(tree: &Tree) -> Vec<(String, std::ops::Range<u32>, Point, Point)> {
+        let mut nodes = Vec::new();
+        walk_tree(tree, |cursor| {
+            let node = cursor.node();
+            nodes.push((
+                node.kind().to_owned(),
+                node.byte_range(),
+                node.start_point(),
+                node.end_point(),
+            ));
+        });
+        nodes
+    }
+    let point = |byte: u32| {
+        let row = new_text.byte_to_line(byte as usize);
+        Point {
+            row: row as u32,
+            col: byte - new_text.line_to_byte(row) as u32,
+        }
+    };
+    let rust = loader.get("rust");
+    let timeout = Duration::from_secs(60);
+    let mut syntax = Syntax::new(old_text.slice(..), rust, timeout, loader).unwrap();
+
+    // The replaced text of each edit in the coordinates of `new_text`.
+    let mut offset = 0;
+    let replacements: Vec<_> = edits
+        .iter()
+        .map(|edit| {
+            let replacement = (edit.start_byte as i64 + offset) as u32
+                ..(edit.new_end_byte as i64 + offset) as u32;
+            offset += edit.new_end_byte as i64 - edit.old_end_byte as i64;
+            replacement
+        })
+        .collect();
+    let mut edited_tree = syntax.tree().clone();
+    for edit in edits.iter().rev() {
+        edited_tree.edit(edit);
+    }
+    for (kind, range, start, end) in nodes(&edited_tree) {
+        for (byte, point_) in [(range.start, start), (range.end, end)] {
+            if !replacements
+                .iter()
+                .any(|replacement| replacement.start < byte && byte < replacement.end)
+            {
+                proptest::prop_assert_eq!(point_, point(byte), "{} {:?}", kind, range);
+            }
+        }
+    }
+
+    syntax
+        .update(new_text.slice(..), timeout, edits, loader)
+        .unwrap();
+    let fresh = Syntax::new(new_text.slice(..), rust, timeout, loader).unwrap();
+    proptest::prop_assert_eq!(nodes(syntax.tree()), nodes(fresh.tree()));
+    Ok(())
+}
+
+#[test]
+fn edits_for_changes() {
+    let loader = TestLanguageLoader::new();
+    let mut runner = TestRunner::new(ProptestConfig::with_cases(128));
+    runner
+        .run(&edit_props::lines_and_ops(), |(lines, ops)| {
+            let (new_lines, changes) = edit_props::apply(&lines, &ops);
+            let old_text = Rope::from(edit_props::render(&lines));
+            let new_text = Rope::from(edit_props::render(&new_lines));
+            let mut applied = old_text.clone();
+            for (range, replacement) in changes.iter().rev() {
+                applied.remove(range.clone());
+                applied.insert(range.start, replacement);
+            }
+            proptest::prop_assert_eq!(&applied, &new_text);
+
+            let edits = edits::edits_for_changes(old_text.slice(..), changes);
+            check_incremental_parse(&loader, &old_text, &new_text, &edits)
+        })
+        .unwrap();
+}
+
+#[test]
+#[cfg(feature = "diff")]
+fn edits_for_diff() {
+    let loader = TestLanguageLoader::new();
+    let mut runner = TestRunner::new(ProptestConfig::with_cases(128));
+    runner
+        .run(&edit_props::lines_and_ops(), |(lines, ops)| {
+            let (new_lines, _) = edit_props::apply(&lines, &ops);
+            let old_text = Rope::from(edit_props::render(&lines));
+            let new_text = Rope::from(edit_props::render(&new_lines));
+            let edits = edits::edits_for_diff(old_text.slice(..), new_text.slice(..));
+            if old_text == new_text {
+                proptest::prop_assert!(edits.is_empty());
+            }
+            check_incremental_parse(&loader, &old_text, &new_text, &edits)
+        })
+        .unwrap();
+}
+
+#[test]
+fn compose_edits() {
+    let loader = TestLanguageLoader::new();
+    let mut runner = TestRunner::new(ProptestConfig::with_cases(128));
+    runner
+        .run(
+            // Inserting lines can double the number of lines.
+            &(edit_props::lines_and_ops(), edit_props::ops(16)),
+            |((lines, first_ops), second_ops)| {
+                let (mid_lines, first) = edit_props::apply(&lines, &first_ops);
+                let (new_lines, second) = edit_props::apply(&mid_lines, &second_ops);
+                let old_text = Rope::from(edit_props::render(&lines));
+                let mid_text = Rope::from(edit_props::render(&mid_lines));
+                let new_text = Rope::from(edit_props::render(&new_lines));
+                let first = edits::edits_for_changes(old_text.slice(..), first);
+                let second = edits::edits_for_changes(mid_text.slice(..), second);
+                let edits = background::compose_edits(&first, &second);
+                check_incremental_parse(&loader, &old_text, &new_text, &edits)
+            },
+        )
+        .unwrap();
+}
@@ -2,10 +2,8 @@
 
 use std::iter;
 
-use ropey::RopeSlice;
-
 use crate::TREE_SITTER_MATCH_LIMIT;
-use tree_sitter::{InactiveQueryCursor, Node, Query, RopeInput};
+use tree_sitter::{ChunkedInput, ChunkedText, InactiveQueryCursor, Node, Query};
 
 #[derive(Debug)]
 pub enum CapturedNode<'a> {
@@ -39,8 +37,10 @@ impl TextObjectQuery {
     /// Run the query on the given node and return sub nodes which match given
     /// capture ("function.inside", "class.around", etc).
     ///
-    /// Captures may contain multiple nodes by using quantifiers (+, *, etc),
-    /// and support for this is partial and could use improvement.
+    /// Captures may contain multiple nodes by using quantifiers (+, *, etc).
+    /// Captures which are quantified by `*` or `+` in the matching pattern
+    /// are returned as [`CapturedNode::Grouped`], all others as
+    /// [`CapturedNode::Single`].
     ///
     /// ```query
     /// (comment)+ @capture
@@ -56,7 +56,7 @@ impl TextObjectQuery {
         &'a self,
         capture_name: &str,
         node: Node<'a>,
-        slice: RopeSlice<'a>,
+        slice: impl ChunkedText<'a> + 'a,
         cursor: InactiveQueryCursor,
     ) -> Option<impl Iterator<Item = CapturedNode<'a>>> {
         self.capture_nodes_any(&[capture_name], node, slice, cursor)
@@ -64,11 +64,15 @@ impl TextObjectQuery {
 
     /// Find the first capture that exists out of all given `capture_names`
     /// and return sub nodes that match this capture.
+    ///
+    /// The ranges, start depth, timeout and progress callback configured on
+    /// `cursor` limit which nodes are returned. The match limit is always set
+    /// to [`TREE_SITTER_MATCH_LIMIT`].
     pub fn capture_nodes_any<'a>(
         &'a self,
         capture_names: &[&str],
         node: Node<'a>,
-        slice: RopeSlice<'a>,
+        slice: impl ChunkedText<'a> + 'a,
         mut cursor: InactiveQueryCursor,
     ) -> Option<impl Iterator<Item = CapturedNode<'a>>> {
         let capture = capture_names
@@ -76,16 +80,23 @@ impl TextObjectQuery {
             .find_map(|cap| self.query.get_capture(cap))?;
 
         cursor.set_match_limit(TREE_SITTER_MATCH_LIMIT);
-        let mut cursor = cursor.execute_query(&self.query, &node, RopeInput::new(slice));
+        let query = &self.query;
+        let mut cursor = cursor.execute_query(query, &node, ChunkedInput::new(slice));
         let capture_node = iter::from_fn(move || {
             let (mat, _) = cursor.next_matched_node()?;
-            Some(mat.nodes_for_capture(capture).cloned().collect())
+            let nodes: Vec<_> = mat.nodes_for_capture(capture).cloned().collect();
+            Some((mat.pattern(), nodes))
         })
-        .filter_map(move |nodes: Vec<_>| {
-            if nodes.len() > 1 {
+        .filter_map(move |(pattern, mut nodes)| {
+            if nodes.is_empty() {
+                return None;
+            }
+            // Captures under a `*` or `+` quantifier are grouped even when they
+            // only matched a single node so that callers can tell them apart.
+            if query.capture_quantifier(pattern, capture).is_repeated() {
                 Some(CapturedNode::Grouped(nodes))
             } else {
-                nodes.into_iter().map(CapturedNode::Single).next()
+                Some(CapturedNode::Single(nodes.swap_remove(0)))
             }
         });
         Some(capture_node)
@@ -1,6 +1,14 @@
-use crate::tree_sitter::Node;
+use crate::tree_sitter::{FieldId, Node};
 use crate::{Layer, Syntax};
 
+/// A cursor which walks the syntax trees of all injection layers of a [`Syntax`] as if they
+/// were a single tree: the root of an injection layer is a child of the node the injection
+/// starts at, and the parent of the root of an injection layer is the root of its parent
+/// layer.
+///
+/// Cloning a cursor is cheap and can be used to save and restore positions, see also
+/// [`TreeCursor::reset_to`].
+#[derive(Clone)]
 pub struct TreeCursor<'tree> {
     syntax: &'tree Syntax,
     current: Layer,
@@ -22,6 +30,78 @@ impl<'tree> TreeCursor<'tree> {
         self.cursor.node()
     }
 
+    /// The injection layer of the current node.
+    pub fn layer(&self) -> Layer {
+        self.current
+    }
+
+    /// Get the field name of the current node within its layer. The roots of injection
+    /// layers never have a field name.
+    pub fn field_name(&self) -> Option<&'tree str> {
+        self.cursor.field_name()
+    }
+
+    /// Get the numerical field id of the current node within its layer.
+    ///
+    /// See also [`TreeCursor::field_name`].
+    pub fn field_id(&self) -> Option<FieldId> {
+        self.cursor.field_id()
+    }
+
+    /// Get the depth of the current node: the number of times [`TreeCursor::goto_parent`]
+    /// succeeds before the cursor reaches the root of the root layer.
+    pub fn depth(&self) -> u32 {
+        let mut depth = self.cursor.depth();
+        let mut layer = self.current;
+        while let Some(parent) = self.syntax.layer(layer).parent {
+            if self.syntax.layer(parent).tree().is_some() {
+                depth += 1;
+            }
+            layer = parent;
+        }
+        depth
+    }
+
+    /// Get the index of the current node out of all descendants (in pre-order) of the root
+    /// of the current layer, see [`TreeCursor::goto_descendant`].
+    pub fn descendant_index(&self) -> u32 {
+        self.cursor.descendant_index()
+    }
+
+    /// Move the cursor to the `index`th descendant (in pre-order) of the root of `layer`.
+    /// Zero is the root of the layer. Returns `false` (without moving the cursor) if the
+    /// layer was not parsed.
+    ///
+    /// Together with [`TreeCursor::layer`] and [`TreeCursor::descendant_index`] this allows
+    /// to save the position of a cursor without borrowing the syntax tree.
+    pub fn goto_descendant(&mut self, layer: Layer, index: u32) -> bool {
+        if layer != self.current {
+            let Some(tree) = self.syntax.layer(layer).tree() else {
+                return false;
+            };
+            self.current = layer;
+            self.cursor = tree.walk();
+        }
+        self.cursor.goto_descendant(index);
+        true
+    }
+
+    /// Move the cursor back to the root of the root layer.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.syntax);
+    }
+
+    /// Move the cursor to the position of `other`, reusing the memory of this cursor if
+    /// both are in the same layer.
+    pub fn reset_to(&mut self, other: &TreeCursor<'tree>) {
+        if self.current == other.current {
+            self.cursor.reset_to(&other.cursor);
+        } else {
+            self.current = other.current;
+            self.cursor = other.cursor.clone();
+        }
+    }
+
     pub fn goto_parent(&mut self) -> bool {
         if self.cursor.goto_parent() {
             return true;
@@ -1,6 +1,6 @@
 use std::fs::{self, File};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
@@ -9,12 +9,15 @@ use sha1::{Digest, Sha1};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
-use crate::{decompress, Metadata, LIB_EXTENSION};
+use crate::{decompress, static_library_name, Metadata, LIB_EXTENSION};
+
+const BUILD_COOKIE: &str = ".BUILD_COOKIE";
+const STATIC_BUILD_COOKIE: &str = ".BUILD_COOKIE_STATIC";
 
 type Checksum = [u8; 20];
-fn is_fresh(grammar_dir: &Path, force: bool) -> Result<(Checksum, bool)> {
+fn is_fresh(grammar_dir: &Path, cookie: &str, force: bool) -> Result<(Checksum, bool)> {
     let src_dir = grammar_dir.join("src");
-    let cookie = grammar_dir.join(".BUILD_COOKIE");
+    let cookie = grammar_dir.join(cookie);
     let mut hasher = Sha1::new();
     for file in WalkDir::new(src_dir) {
         let file = file?;
@@ -138,19 +141,10 @@ impl CompilerCommand {
     }
 }
 
-pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Result<()> {
-    let src_dir = grammar_dir.join("src");
-    let mut parser = src_dir.join("parser.c");
-    ensure!(
-        parser.exists(),
-        "failed to compile {grammar_name}: {} not found!",
-        parser.display()
-    );
-    let (hash, fresh) = is_fresh(grammar_dir, force)?;
-    if fresh {
-        return Ok(());
-    }
-    let build_dir = TempDir::new().context("failed to create temporary build directory")?;
+/// Returns the path of the `parser.c` file of the grammar, decompressing it into
+/// `build_dir` if necessary.
+fn prepare_parser(grammar_name: &str, grammar_dir: &Path, build_dir: &TempDir) -> Result<PathBuf> {
+    let mut parser = grammar_dir.join("src").join("parser.c");
     let metadata = Metadata::read(&grammar_dir.join("metadata.json"))
         .with_context(|| format!("failed to read metadata for {grammar_name}"))?;
     let Some(parser_definition) = metadata.parser_definition() else {
@@ -172,6 +166,23 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
             })?;
         parser = decompressed_parser;
     }
+    Ok(parser)
+}
+
+pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Result<()> {
+    let src_dir = grammar_dir.join("src");
+    let parser = src_dir.join("parser.c");
+    ensure!(
+        parser.exists(),
+        "failed to compile {grammar_name}: {} not found!",
+        parser.display()
+    );
+    let (hash, fresh) = is_fresh(grammar_dir, BUILD_COOKIE, force)?;
+    if fresh {
+        return Ok(());
+    }
+    let build_dir = TempDir::new().context("failed to create temporary build directory")?;
+    let parser = prepare_parser(grammar_name, grammar_dir, &build_dir)?;
     let mut commands = Vec::new();
     let mut obj_files = Vec::new();
     if src_dir.join("scanner.c").exists() {
@@ -221,6 +232,72 @@ pub fn build_grammar(grammar_name: &str, grammar_dir: &Path, force: bool) -> Res
             to.display()
         )
     })?;
-    let _ = fs::write(grammar_dir.join(".BUILD_COOKIE"), hash);
+    let _ = fs::write(grammar_dir.join(BUILD_COOKIE), hash);
+    Ok(())
+}
+
+/// Compiles the grammar into a static archive (see [`static_library_name`]) which can be
+/// linked into a binary instead of being loaded at runtime.
+pub fn build_grammar_static(grammar_name: &str, grammar_dir: &Path, force: bool) -> Result<()> {
+    let src_dir = grammar_dir.join("src");
+    let parser = src_dir.join("parser.c");
+    ensure!(
+        parser.exists(),
+        "failed to compile {grammar_name}: {} not found!",
+        parser.display()
+    );
+    let (hash, fresh) = is_fresh(grammar_dir, STATIC_BUILD_COOKIE, force)?;
+    if fresh {
+        return Ok(());
+    }
+    let build_dir = TempDir::new().context("failed to create temporary build directory")?;
+    let parser = prepare_parser(grammar_name, grammar_dir, &build_dir)?;
+    let new_build = |cpp: bool| {
+        let mut build = cc::Build::new();
+        build
+            .cpp(cpp)
+            .std(if cpp { "c++14" } else { "c11" })
+            .opt_level(3)
+            .debug(false)
+            .warnings(false)
+            .cargo_metadata(false)
+            .cargo_warnings(false)
+            .host(BUILD_TARGET)
+            .target(BUILD_TARGET)
+            .out_dir(build_dir.path())
+            .include(&src_dir);
+        build
+    };
+    let mut c_build = new_build(false);
+    c_build.file(&parser);
+    if src_dir.join("scanner.c").exists() {
+        c_build.file(src_dir.join("scanner.c"));
+    }
+    let mut objects = c_build
+        .try_compile_intermediates()
+        .with_context(|| format!("failed to compile {grammar_name}"))?;
+    if src_dir.join("scanner.cc").exists() {
+        let cpp_objects = new_build(true)
+            .file(src_dir.join("scanner.cc"))
+            .try_compile_intermediates()
+            .with_context(|| format!("failed to compile the scanner of {grammar_name}"))?;
+        objects.extend(cpp_objects);
+    }
+    new_build(false)
+        .objects(objects)
+        .try_compile(grammar_name)
+        .with_context(|| format!("failed to create static archive for {grammar_name}"))?;
+
+    let lib_name = static_library_name(grammar_name);
+    let from = build_dir.path().join(&lib_name);
+    let to = grammar_dir.join(&lib_name);
+    fs::copy(&from, &to).with_context(|| {
+        format!(
+            "failed to copy static archive from {} to {}",
+            from.display(),
+            to.display()
+        )
+    })?;
+    let _ = fs::write(grammar_dir.join(STATIC_BUILD_COOKIE), hash);
     Ok(())
 }
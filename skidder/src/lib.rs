@@ -22,6 +22,15 @@ const LIB_EXTENSION: &str = "dll";
 
 mod build;
 
+/// The file name of the static archive created by [`build_grammar_static`].
+pub fn static_library_name(grammar: &str) -> String {
+    if cfg!(target_env = "msvc") {
+        format!("{grammar}.lib")
+    } else {
+        format!("lib{grammar}.a")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub repos: Vec<Repo>,
@@ -252,6 +261,82 @@ pub fn build_grammar(config: &Config, grammar: &str, force_rebuild: bool) -> Res
     bail!("grammar not found in any configured repository")
 }
 
+/// Like [`build_grammar`] but compiles the grammar into a static archive and returns its
+/// path. The archive can be linked into a binary (see [`generate_static_module`]) so that
+/// the grammar doesn't need to be loaded at runtime.
+pub fn build_grammar_static(
+    config: &Config,
+    grammar: &str,
+    force_rebuild: bool,
+) -> Result<PathBuf> {
+    for repo in &config.repos {
+        if repo.has_grammar(config, grammar) {
+            let grammar_dir = repo.dir(config).join(grammar);
+            build::build_grammar_static(grammar, &grammar_dir, force_rebuild)?;
+            return Ok(grammar_dir.join(static_library_name(grammar)));
+        }
+    }
+    bail!("grammar not found in any configured repository")
+}
+
+/// Generates a Rust module which declares the `tree_sitter_<name>` functions of the given
+/// (statically linked) grammars and exports them as a
+/// `pub const GRAMMARS: &[(&str, LanguageFn)]` which maps the grammar names to their
+/// `LanguageFn`. `bindings_crate` is the path of the `tree-house-bindings` crate, for
+/// example `tree_house::tree_sitter`.
+///
+/// The archives must be linked by the build script, for example:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let config = skidder::Config {
+///     repos: vec![skidder::Repo::Local {
+///         path: "grammars".into(),
+///     }],
+///     index: std::path::PathBuf::new(),
+///     verbose: false,
+/// };
+/// let grammars = ["rust", "toml"];
+/// for grammar in grammars {
+///     let archive = skidder::build_grammar_static(&config, grammar, false)?;
+///     let dir = archive.parent().unwrap();
+///     println!("cargo:rustc-link-search=native={}", dir.display());
+///     println!("cargo:rustc-link-lib=static={grammar}");
+/// }
+/// let module = skidder::generate_static_module(&grammars, "tree_house::tree_sitter");
+/// let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+/// std::fs::write(out_dir.join("grammars.rs"), module)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The module can then be included with
+/// `mod grammars { include!(concat!(env!("OUT_DIR"), "/grammars.rs")); }`. Grammars with
+/// C++ scanners additionally require linking the C++ standard library.
+pub fn generate_static_module(grammars: &[&str], bindings_crate: &str) -> String {
+    let mut module = String::from("// @generated by skidder, do not edit.\n\n");
+    module.push_str("extern \"C\" {\n");
+    for grammar in grammars {
+        let symbol = format!("tree_sitter_{}", grammar.replace('-', "_"));
+        module.push_str(&format!(
+            "    fn {symbol}() -> *const ::std::ffi::c_void;\n"
+        ));
+    }
+    module.push_str("}\n\n");
+    module.push_str(&format!(
+        "/// The grammars which were statically linked into the binary by name.\n\
+         pub const GRAMMARS: &[(&str, {bindings_crate}::LanguageFn)] = &[\n"
+    ));
+    for grammar in grammars {
+        let symbol = format!("tree_sitter_{}", grammar.replace('-', "_"));
+        module.push_str(&format!(
+            "    ({grammar:?}, unsafe {{ {bindings_crate}::LanguageFn::from_raw({symbol}) }}),\n"
+        ));
+    }
+    module.push_str("];\n");
+    module
+}
+
 pub fn list_grammars(config: &Config) -> Result<Vec<PathBuf>> {
     let mut res = Vec::new();
     for repo in &config.repos {
@@ -266,6 +351,30 @@ pub fn build_all_grammars(
     config: &Config,
     force_rebuild: bool,
     concurrency: Option<NonZeroUsize>,
+) -> Result<usize> {
+    build_all(config, force_rebuild, concurrency, build::build_grammar)
+}
+
+/// Like [`build_all_grammars`] but compiles the grammars into static archives, see
+/// [`build_grammar_static`].
+pub fn build_all_grammars_static(
+    config: &Config,
+    force_rebuild: bool,
+    concurrency: Option<NonZeroUsize>,
+) -> Result<usize> {
+    build_all(
+        config,
+        force_rebuild,
+        concurrency,
+        build::build_grammar_static,
+    )
+}
+
+fn build_all(
+    config: &Config,
+    force_rebuild: bool,
+    concurrency: Option<NonZeroUsize>,
+    build_grammar: fn(&str, &Path, bool) -> Result<()>,
 ) -> Result<usize> {
     let grammars = list_grammars(config)?;
     let bar = ProgressBar::new(grammars.len() as u64).with_style(
@@ -281,18 +390,17 @@ pub fn build_all_grammars(
     let failed = Mutex::new(Vec::new());
     thread::scope(|scope| {
         for _ in 0..concurrency {
-            scope.spawn(|| loop {
-                let Some(grammar) = grammars.get(i.fetch_add(1, atomic::Ordering::Relaxed)) else {
-                    break;
-                };
-                let name = grammar.file_name().unwrap().to_str().unwrap();
-                if let Err(err) = build::build_grammar(name, grammar, force_rebuild) {
-                    for err in err.chain() {
-                        bar.println(format!("error: {err}"))
+            scope.spawn(|| {
+                while let Some(grammar) = grammars.get(i.fetch_add(1, atomic::Ordering::Relaxed)) {
+                    let name = grammar.file_name().unwrap().to_str().unwrap();
+                    if let Err(err) = build_grammar(name, grammar, force_rebuild) {
+                        for err in err.chain() {
+                            bar.println(format!("error: {err}"))
+                        }
+                        failed.lock().unwrap().push(name.to_owned())
                     }
-                    failed.lock().unwrap().push(name.to_owned())
+                    bar.inc(1);
                 }
-                bar.inc(1);
             });
         }
     });
@@ -0,0 +1,26 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use skidder::Repo;
+
+fn main() {
+    // `./test-grammars` in the root of the repo.
+    let path = Path::new("../test-grammars").canonicalize().unwrap();
+    println!("cargo:rerun-if-changed={}", path.join("comment").display());
+    let config = skidder::Config {
+        repos: vec![Repo::Local { path }],
+        index: PathBuf::new(),
+        verbose: false,
+    };
+    let grammars = ["comment"];
+    for grammar in grammars {
+        let archive = skidder::build_grammar_static(&config, grammar, false).unwrap();
+        let dir = archive.parent().unwrap();
+        println!("cargo:rustc-link-search=native={}", dir.display());
+        println!("cargo:rustc-link-lib=static={grammar}");
+    }
+    let module = skidder::generate_static_module(&grammars, "tree_sitter");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out_dir.join("grammars.rs"), module).unwrap();
+}
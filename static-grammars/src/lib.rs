@@ -0,0 +1,30 @@
+//! Links the comment grammar of `test-grammars` statically with the archive and module
+//! generated by skidder in the build script, like a downstream crate would.
+
+mod grammars {
+    include!(concat!(env!("OUT_DIR"), "/grammars.rs"));
+}
+
+pub use grammars::GRAMMARS;
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::{Grammar, Parser};
+
+    use crate::GRAMMARS;
+
+    #[test]
+    fn parse_with_static_grammar() {
+        let [(name, language_fn)] = GRAMMARS else {
+            panic!("expected a single grammar");
+        };
+        assert_eq!(*name, "comment");
+        let grammar = Grammar::from_language_fn(*language_fn).unwrap();
+        let mut parser = Parser::new();
+        parser.set_grammar(grammar).unwrap();
+        let tree = parser.parse("TODO: link statically", None).unwrap();
+        let root = tree.root_node();
+        assert_eq!(root.kind(), "source");
+        assert_eq!(root.child(0).unwrap().kind(), "tag");
+    }
+}